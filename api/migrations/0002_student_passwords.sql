@@ -0,0 +1,3 @@
+-- The Argon2 hash of the password chosen by the student, in the PHC string
+-- format. As long as it is NULL, the student logs in with the shared password.
+ALTER TABLE students ADD COLUMN password_hash TEXT;
@@ -0,0 +1,10 @@
+CREATE TABLE sessions (
+    id INTEGER PRIMARY KEY,
+    student_id INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    -- Set when the student logged out or when the session was revoked. The
+    -- tokens of a revoked session are refused even if they did not expire.
+    revoked_at TIMESTAMP,
+    FOREIGN KEY (student_id) REFERENCES students(id)
+);
@@ -0,0 +1,4 @@
+-- 0: Student
+-- 1: Teacher, who can block exercises, mark them as corrected and moderate the
+--    correction pictures.
+ALTER TABLE students ADD COLUMN role INTEGER NOT NULL DEFAULT 0;
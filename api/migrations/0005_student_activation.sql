@@ -0,0 +1,3 @@
+-- Students who left the class are deactivated rather than deleted so that
+-- their reservations and corrections are kept. They cannot log in.
+ALTER TABLE students ADD COLUMN active BOOLEAN NOT NULL DEFAULT TRUE;
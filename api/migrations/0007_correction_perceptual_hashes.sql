@@ -0,0 +1,3 @@
+-- A difference hash of the pictures, to detect near-identical uploads. It is
+-- NULL for the pictures uploaded before it existed.
+ALTER TABLE exercise_corrections ADD COLUMN perceptual_hash INTEGER;
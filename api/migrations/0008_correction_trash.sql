@@ -0,0 +1,3 @@
+-- Deleted corrections are kept for a while so that they can be restored.
+ALTER TABLE exercise_corrections ADD COLUMN deleted_at TIMESTAMP;
+ALTER TABLE exercise_corrections ADD COLUMN deleted_by INTEGER REFERENCES students(id);
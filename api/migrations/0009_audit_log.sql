@@ -0,0 +1,27 @@
+-- Append-only record of the actions that change the state of the application.
+-- The actor and the units are not foreign keys since the log outlives them.
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- NULL for the commands run on the command line.
+    actor_id INTEGER,
+    action TEXT NOT NULL,
+    unit_id INTEGER,
+    exercise_index INTEGER,
+    -- JSON values, NULL when they do not apply to the action.
+    old_value TEXT,
+    new_value TEXT,
+    -- From the header configured with REAL_IP_HEADER.
+    ip TEXT
+);
+CREATE INDEX audit_log_actor ON audit_log (actor_id);
+CREATE INDEX audit_log_exercise ON audit_log (unit_id, exercise_index);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
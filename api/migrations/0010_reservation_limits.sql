@@ -0,0 +1,7 @@
+-- Limits on the reservations of the exercises of a unit, NULL when there is
+-- no limit.
+ALTER TABLE units ADD COLUMN max_reservers_per_exercise INTEGER;
+-- Whether the maximum number of reservers of an exercise applies to each
+-- group separately, since the groups present on different days.
+ALTER TABLE units ADD COLUMN max_reservers_per_group BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE units ADD COLUMN max_reservations_per_student INTEGER;
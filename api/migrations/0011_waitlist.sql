@@ -0,0 +1,4 @@
+-- The state of exercise_student_state can also be 2 for a student waiting for
+-- a reservation of the exercise to be released. The students are promoted in
+-- the order given by this column, which is only set for them.
+ALTER TABLE exercise_student_state ADD COLUMN waitlist_order INTEGER;
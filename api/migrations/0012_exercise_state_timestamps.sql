@@ -0,0 +1,33 @@
+-- When the exercise was reserved and presented. A student who presents an
+-- exercise keeps the time of their reservation, and the times are NULL when
+-- unknown, such as for a presentation without a reservation.
+ALTER TABLE exercise_student_state ADD COLUMN reserved_at TIMESTAMP;
+ALTER TABLE exercise_student_state ADD COLUMN presented_at TIMESTAMP;
+
+-- The existing rows get the time of the last matching change in the audit
+-- log, if any. The values of the log were the bare state name before teachers
+-- could change the state of other students.
+UPDATE exercise_student_state SET reserved_at = (
+    SELECT MAX(a.created_at) FROM audit_log a
+    WHERE a.action = 'setExerciseState'
+    AND a.unit_id = exercise_student_state.unit_id
+    AND a.exercise_index = exercise_student_state.exercise_index
+    AND CASE WHEN json_type(a.new_value) = 'object'
+        THEN json_extract(a.new_value, '$.studentId') = exercise_student_state.student_id
+            AND json_extract(a.new_value, '$.state') = 'reserved'
+        ELSE a.actor_id = exercise_student_state.student_id
+            AND json_extract(a.new_value, '$') = 'reserved'
+    END
+) WHERE state IN (0, 1);
+UPDATE exercise_student_state SET presented_at = (
+    SELECT MAX(a.created_at) FROM audit_log a
+    WHERE a.action = 'setExerciseState'
+    AND a.unit_id = exercise_student_state.unit_id
+    AND a.exercise_index = exercise_student_state.exercise_index
+    AND CASE WHEN json_type(a.new_value) = 'object'
+        THEN json_extract(a.new_value, '$.studentId') = exercise_student_state.student_id
+            AND json_extract(a.new_value, '$.state') = 'presented'
+        ELSE a.actor_id = exercise_student_state.student_id
+            AND json_extract(a.new_value, '$') = 'presented'
+    END
+) WHERE state = 1;
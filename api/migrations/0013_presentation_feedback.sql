@@ -0,0 +1,7 @@
+-- The grade and the comment of the teacher on a presentation, only for the
+-- rows with state 1. The scale is kept with the grade since it can be
+-- configured.
+ALTER TABLE exercise_student_state ADD COLUMN grade REAL;
+ALTER TABLE exercise_student_state ADD COLUMN max_grade INTEGER;
+ALTER TABLE exercise_student_state ADD COLUMN feedback TEXT;
+ALTER TABLE exercise_student_state ADD COLUMN graded_at TIMESTAMP;
@@ -0,0 +1,15 @@
+-- Notices for the students about the changes that someone else made to their
+-- exercises, such as a reservation cancelled because the teacher blocked the
+-- exercise. They are kept until the student dismisses them.
+CREATE TABLE exercise_notices (
+    id INTEGER PRIMARY KEY,
+    student_id INTEGER NOT NULL,
+    unit_id INTEGER NOT NULL,
+    exercise_index INTEGER NOT NULL,
+    -- For example "reservationCancelled" or "waitlistCancelled".
+    kind TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (student_id) REFERENCES students(id),
+    FOREIGN KEY (unit_id) REFERENCES units(id)
+);
+CREATE INDEX exercise_notices_student ON exercise_notices (student_id, unit_id);
@@ -0,0 +1,5 @@
+-- A one-time code given by a teacher to a student, which the student needs to
+-- choose their own password. The shared password is not enough, since all the
+-- students of the class know it. Only the Argon2 hash of the code is stored.
+ALTER TABLE students ADD COLUMN password_setup_code_hash TEXT;
+ALTER TABLE students ADD COLUMN password_setup_code_expires_at TIMESTAMP;
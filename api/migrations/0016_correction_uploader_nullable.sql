@@ -0,0 +1,21 @@
+-- The corrections of a deleted student go to the trash instead of being
+-- deleted with the student, so the uploader of a correction may be unknown.
+-- SQLite cannot drop a NOT NULL constraint, so the table is rebuilt.
+CREATE TABLE exercise_corrections_new (
+    id INTEGER PRIMARY KEY,
+    unit_id INTEGER NOT NULL,
+    unit_exercise INTEGER NOT NULL,
+    created_by INTEGER,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    picture_digest TEXT NOT NULL,
+    perceptual_hash INTEGER,
+    deleted_at TIMESTAMP,
+    deleted_by INTEGER REFERENCES students(id),
+    FOREIGN KEY (unit_id) REFERENCES units(id),
+    FOREIGN KEY (created_by) REFERENCES students(id),
+    UNIQUE(unit_id, unit_exercise, picture_digest)
+);
+INSERT INTO exercise_corrections_new (id, unit_id, unit_exercise, created_by, created_at, picture_digest, perceptual_hash, deleted_at, deleted_by)
+    SELECT id, unit_id, unit_exercise, created_by, created_at, picture_digest, perceptual_hash, deleted_at, deleted_by FROM exercise_corrections;
+DROP TABLE exercise_corrections;
+ALTER TABLE exercise_corrections_new RENAME TO exercise_corrections;
@@ -1,57 +1,5 @@
-CREATE TABLE students (
-    id INTEGER PRIMARY KEY,
-    username TEXT NOT NULL UNIQUE,
-    full_name TEXT NOT NULL,
-    in_group_even BOOLEAN NOT NULL
-);
-
-CREATE TABLE units (
-    id INTEGER PRIMARY KEY,
-    name TEXT NOT NULL,
-    exercise_count INTEGER NOT NULL,
-    -- For each group, the date when the students have to present their
-    -- exercises.
-    deadline_group_even TEXT NOT NULL,
-    deadline_group_odd TEXT NOT NULL
-);
-
-CREATE TABLE exercise (
-    unit_id INTEGER NOT NULL,
-    index_ INTEGER NOT NULL,
-    -- For whatever reason, the teacher said that students should not do this
-    -- exercise.
-    blocked BOOLEAN NOT NULL DEFAULT FALSE,
-    -- Corrected by the teacher for the even group
-    teacher_corrected_for_group_even BOOLEAN NOT NULL DEFAULT FALSE,
-    -- Corrected by the teacher for the odd group
-    teacher_corrected_for_group_odd BOOLEAN NOT NULL DEFAULT FALSE,
-    FOREIGN KEY (unit_id) REFERENCES units(id),
-    UNIQUE(unit_id, index_)
-);
-
-CREATE TABLE exercise_student_state (
-    student_id INTEGER NOT NULL,
-    unit_id INTEGER NOT NULL,
-    exercise_index INTEGER NOT NULL,
-    -- 0: Reserved by the student
-    -- 1: Presented by the student
-    state INTEGER NOT NULL,
-    FOREIGN KEY (student_id) REFERENCES students(id),
-    FOREIGN KEY (unit_id) REFERENCES units(id),
-    UNIQUE(student_id, unit_id, exercise_index)
-);
-
-CREATE TABLE exercise_corrections (
-    id INTEGER PRIMARY KEY,
-    unit_id INTEGER NOT NULL,
-    unit_exercise INTEGER NOT NULL,
-    created_by INTEGER NOT NULL,
-    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
-    picture_digest TEXT NOT NULL,
-    FOREIGN KEY (unit_id) REFERENCES units(id),
-    FOREIGN KEY (created_by) REFERENCES students(id),
-    UNIQUE(unit_id, unit_exercise, picture_digest)
-);
+-- Sample data for a development database. The schema itself is created by
+-- the API on startup.
 
 INSERT INTO students (username, full_name, in_group_even) VALUES ("antoine", "Cybélia Antoine", false);
 INSERT INTO students (username, full_name, in_group_even) VALUES ("audoin", "Anatol Audoin", false);
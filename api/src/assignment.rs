@@ -0,0 +1,253 @@
+//! Automatic assignment of the exercises of a unit from the preferences of the
+//! students, so that reserving is not a race to grab the easy exercises.
+//!
+//! The students pick their exercises in turns, in an order drawn at random
+//! from a seed. The order alternates between the groups and is reversed at
+//! every round, so that the students who pick first in a round pick last in
+//! the next one.
+
+use std::collections::HashSet;
+
+/// A student taking part in the assignment.
+pub(crate) struct Candidate {
+    pub id: u32,
+    pub in_group_even: bool,
+    /// The exercises that the student wants, the preferred one first.
+    pub preferences: Vec<u32>,
+    /// How many more exercises the student should get.
+    pub wanted: u32,
+    /// The exercises that the student already reserved, presented or waits
+    /// for, which cannot be given to them again.
+    pub taken: HashSet<u32>,
+}
+
+/// The state of the exercises of the unit before the assignment.
+pub(crate) struct Exercises {
+    /// The maximum number of reservers of an exercise, if any.
+    pub max_reservers: Option<u32>,
+    /// Whether `max_reservers` applies to each group separately.
+    pub per_group: bool,
+    /// For every exercise, the number of students of the even and of the odd
+    /// group who reserved it.
+    pub reservers: Vec<(u32, u32)>,
+    /// For every exercise, whether the teacher said not to do it.
+    pub blocked: Vec<bool>,
+}
+
+impl Exercises {
+    fn has_room(&self, exercise_index: u32, in_group_even: bool) -> bool {
+        let max = match self.max_reservers {
+            Some(val) => val,
+            None => return true,
+        };
+        let (even, odd) = self.reservers[exercise_index as usize];
+        let count = match (self.per_group, in_group_even) {
+            (false, _) => even + odd,
+            (true, true) => even,
+            (true, false) => odd,
+        };
+        count < max
+    }
+
+    fn reserve(&mut self, exercise_index: u32, in_group_even: bool) {
+        let (even, odd) = &mut self.reservers[exercise_index as usize];
+        if in_group_even {
+            *even += 1;
+        } else {
+            *odd += 1;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Assignment {
+    pub student_id: u32,
+    pub exercise_index: u32,
+    /// The position of the exercise in the preferences of the student,
+    /// starting at 1.
+    pub rank: u32,
+}
+
+/// SplitMix64, which is enough to shuffle a class and which is implemented
+/// here so that a seed gives the same assignment in every version.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..n`. The bias is negligible for a class.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+/// The order in which the students pick their exercises: shuffled within each
+/// group and alternating between the groups, starting with a random one.
+fn draft_order(candidates: &[Candidate], rng: &mut Rng) -> Vec<usize> {
+    let mut even: Vec<usize> = Vec::new();
+    let mut odd: Vec<usize> = Vec::new();
+    let mut by_id: Vec<usize> = (0..candidates.len()).collect();
+    // The order of the input must not matter.
+    by_id.sort_by_key(|&i| candidates[i].id);
+    for i in by_id {
+        if candidates[i].in_group_even {
+            even.push(i);
+        } else {
+            odd.push(i);
+        }
+    }
+    rng.shuffle(&mut even);
+    rng.shuffle(&mut odd);
+    let (first, second) = if rng.below(2) == 0 {
+        (even, odd)
+    } else {
+        (odd, even)
+    };
+
+    let mut order = Vec::with_capacity(candidates.len());
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => order.extend(a.into_iter().chain(b)),
+        }
+    }
+    order
+}
+
+/// Assigns exercises to the candidates. The same input and seed always give
+/// the same assignment.
+pub(crate) fn assign(
+    candidates: &[Candidate],
+    exercises: &mut Exercises,
+    seed: u64,
+) -> Vec<Assignment> {
+    let mut rng = Rng(seed);
+    let mut order = draft_order(candidates, &mut rng);
+    let mut wanted: Vec<u32> = candidates.iter().map(|c| c.wanted).collect();
+    let mut taken: Vec<HashSet<u32>> = candidates.iter().map(|c| c.taken.clone()).collect();
+    let mut result = Vec::new();
+
+    loop {
+        let mut progress = false;
+        for &i in &order {
+            if wanted[i] == 0 {
+                continue;
+            }
+            let c = &candidates[i];
+            let choice = c.preferences.iter().enumerate().find(|(_, &e)| {
+                (e as usize) < exercises.blocked.len()
+                    && !exercises.blocked[e as usize]
+                    && !taken[i].contains(&e)
+                    && exercises.has_room(e, c.in_group_even)
+            });
+            if let Some((rank, &e)) = choice {
+                exercises.reserve(e, c.in_group_even);
+                taken[i].insert(e);
+                wanted[i] -= 1;
+                result.push(Assignment {
+                    student_id: c.id,
+                    exercise_index: e,
+                    rank: rank as u32 + 1,
+                });
+                progress = true;
+            }
+        }
+        if !progress {
+            break;
+        }
+        order.reverse();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: u32, in_group_even: bool, preferences: &[u32]) -> Candidate {
+        Candidate {
+            id,
+            in_group_even,
+            preferences: preferences.to_vec(),
+            wanted: 1,
+            taken: HashSet::new(),
+        }
+    }
+
+    fn exercises(count: usize, max_reservers: Option<u32>, per_group: bool) -> Exercises {
+        Exercises {
+            max_reservers,
+            per_group,
+            reservers: vec![(0, 0); count],
+            blocked: vec![false; count],
+        }
+    }
+
+    #[test]
+    fn is_deterministic() {
+        let candidates: Vec<Candidate> = (0..10)
+            .map(|id| candidate(id, id % 2 == 0, &[0, 1, 2, 3, 4]))
+            .collect();
+        let first = assign(&candidates, &mut exercises(5, Some(2), false), 42);
+        let again = assign(&candidates, &mut exercises(5, Some(2), false), 42);
+        assert_eq!(first, again);
+        assert_eq!(first.len(), 10);
+
+        let reversed: Vec<Candidate> = candidates
+            .iter()
+            .rev()
+            .map(|c| candidate(c.id, c.in_group_even, &c.preferences))
+            .collect();
+        let mut from_reversed = assign(&reversed, &mut exercises(5, Some(2), false), 42);
+        let mut first = first;
+        first.sort_by_key(|a| a.student_id);
+        from_reversed.sort_by_key(|a| a.student_id);
+        assert_eq!(first, from_reversed);
+    }
+
+    #[test]
+    fn respects_limits() {
+        let candidates: Vec<Candidate> = (0..6).map(|id| candidate(id, id < 3, &[0, 1])).collect();
+        let mut ex = exercises(3, Some(1), true);
+        ex.blocked[1] = true;
+        let result = assign(&candidates, &mut ex, 7);
+        // One student of each group gets the only exercise they can have.
+        assert_eq!(result.len(), 2);
+        assert!(result.iter().all(|a| a.exercise_index == 0 && a.rank == 1));
+        assert_ne!(result[0].student_id < 3, result[1].student_id < 3);
+    }
+
+    #[test]
+    fn alternates_turns() {
+        let mut candidates: Vec<Candidate> = (0..2)
+            .map(|id| candidate(id, true, &[0, 1, 2, 3]))
+            .collect();
+        for c in &mut candidates {
+            c.wanted = 2;
+        }
+        let result = assign(&candidates, &mut exercises(4, Some(1), false), 1);
+        // Whoever picks first in the first round picks last in the second.
+        let first = result[0].student_id;
+        assert_eq!(result[0].exercise_index, 0);
+        assert_eq!(result[1].exercise_index, 1);
+        assert_eq!(result[2].student_id, result[1].student_id);
+        assert_eq!(result[2].exercise_index, 2);
+        assert_eq!(result[3].student_id, first);
+        assert_eq!(result[3].exercise_index, 3);
+    }
+}
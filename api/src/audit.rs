@@ -0,0 +1,133 @@
+//! The audit log, an append-only record of the actions that change the state
+//! of the application, so that one can tell afterwards who did what.
+
+use hyper::{Body, Request};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::http_helpers::client_ip;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Action {
+    SetExerciseState,
+    SetExerciseBlocked,
+    SetTeacherCorrectedForGroupEven,
+    SetTeacherCorrectedForGroupOdd,
+    GradePresentation,
+    SubmitCorrection,
+    DeleteCorrection,
+    RestoreCorrection,
+    CreateUnit,
+    PatchUnit,
+    DeleteUnit,
+    CreateStudent,
+    PatchStudent,
+    DeleteStudent,
+    ImportStudents,
+    ChangePassword,
+    ResetPassword,
+    IssuePasswordSetupCode,
+    RevokeSessions,
+    SetRole,
+}
+
+impl Action {
+    /// The name of the action in the log, which must never change.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::SetExerciseState => "setExerciseState",
+            Action::SetExerciseBlocked => "setExerciseBlocked",
+            Action::SetTeacherCorrectedForGroupEven => "setTeacherCorrectedForGroupEven",
+            Action::SetTeacherCorrectedForGroupOdd => "setTeacherCorrectedForGroupOdd",
+            Action::GradePresentation => "gradePresentation",
+            Action::SubmitCorrection => "submitCorrection",
+            Action::DeleteCorrection => "deleteCorrection",
+            Action::RestoreCorrection => "restoreCorrection",
+            Action::CreateUnit => "createUnit",
+            Action::PatchUnit => "patchUnit",
+            Action::DeleteUnit => "deleteUnit",
+            Action::CreateStudent => "createStudent",
+            Action::PatchStudent => "patchStudent",
+            Action::DeleteStudent => "deleteStudent",
+            Action::ImportStudents => "importStudents",
+            Action::ChangePassword => "changePassword",
+            Action::ResetPassword => "resetPassword",
+            Action::IssuePasswordSetupCode => "issuePasswordSetupCode",
+            Action::RevokeSessions => "revokeSessions",
+            Action::SetRole => "setRole",
+        }
+    }
+}
+
+/// An entry of the audit log, before it is recorded.
+pub(crate) struct Event {
+    action: Action,
+    unit_id: Option<u32>,
+    exercise_index: Option<u32>,
+    old_value: Option<Value>,
+    new_value: Option<Value>,
+}
+
+impl Event {
+    pub fn new(action: Action) -> Self {
+        Event {
+            action,
+            unit_id: None,
+            exercise_index: None,
+            old_value: None,
+            new_value: None,
+        }
+    }
+
+    pub fn unit(mut self, unit_id: u32) -> Self {
+        self.unit_id = Some(unit_id);
+        self
+    }
+
+    pub fn exercise(mut self, unit_id: u32, exercise_index: u32) -> Self {
+        self.unit_id = Some(unit_id);
+        self.exercise_index = Some(exercise_index);
+        self
+    }
+
+    pub fn old_value<T: ?Sized + Serialize>(mut self, value: &T) -> Self {
+        self.old_value = Some(serde_json::to_value(value).unwrap());
+        self
+    }
+
+    pub fn new_value<T: ?Sized + Serialize>(mut self, value: &T) -> Self {
+        self.new_value = Some(serde_json::to_value(value).unwrap());
+        self
+    }
+}
+
+/// Records an event in the audit log. The actor is missing for the commands
+/// run on the command line, and so is the IP address.
+pub(crate) fn record(db: &Connection, actor_id: Option<u32>, ip: Option<&str>, event: Event) {
+    db.execute(
+        "INSERT INTO audit_log (actor_id, action, unit_id, exercise_index, old_value, new_value, ip) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params![
+            actor_id,
+            event.action.name(),
+            event.unit_id,
+            event.exercise_index,
+            event.old_value.map(|v| v.to_string()),
+            event.new_value.map(|v| v.to_string()),
+            ip
+        ],
+    )
+    .unwrap();
+}
+
+/// Records an event caused by a request made by a student.
+pub(crate) fn record_for_req(
+    db: &Connection,
+    req: &Request<Body>,
+    config: &Config,
+    actor_id: u32,
+    event: Event,
+) {
+    record(db, Some(actor_id), client_ip(req, config), event);
+}
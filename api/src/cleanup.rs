@@ -0,0 +1,247 @@
+//! Cleanup of photographed paper corrections.
+//!
+//! Photos of paper are taken under uneven light, at a slight angle and with
+//! wide margins. They are turned into a straight grayscale picture on a white
+//! background, which is easier to read and much smaller once compressed.
+
+use image::{imageops, DynamicImage, GenericImageView, GrayImage, Luma};
+
+/// The size to which the picture is downscaled to estimate the brightness of
+/// the paper, which varies slowly.
+const BACKGROUND_SIZE: u32 = 128;
+
+/// The size to which the picture is downscaled to estimate its skew.
+const DESKEW_SIZE: u32 = 800;
+
+/// Skews larger than this are assumed to be intended, such as a picture that
+/// is sideways, and are left alone.
+const MAX_SKEW_DEGREES: f32 = 8.0;
+
+const SKEW_STEP_DEGREES: f32 = 0.25;
+
+/// Pixels darker than this after normalization are considered ink.
+const INK_THRESHOLD: u8 = 160;
+
+/// The margin left around the content when cropping, relative to the size of
+/// the picture.
+const CROP_MARGIN: f32 = 0.02;
+
+/// Cleans up a photo of a paper correction.
+pub(crate) fn clean_up(image: &DynamicImage) -> DynamicImage {
+    let gray = normalize(&image.to_luma8());
+    let gray = match estimate_skew(&gray) {
+        Some(angle) => rotate(&gray, -angle),
+        None => gray,
+    };
+    DynamicImage::ImageLuma8(crop_margins(gray))
+}
+
+/// Divides every pixel by the brightness of the paper around it, which
+/// removes shadows, and then stretches the contrast so that the paper is
+/// white.
+fn normalize(gray: &GrayImage) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let scale = (BACKGROUND_SIZE as f32 / width.max(height) as f32).min(1.0);
+    let small_width = ((width as f32 * scale) as u32).max(1);
+    let small_height = ((height as f32 * scale) as u32).max(1);
+    let small = imageops::resize(
+        gray,
+        small_width,
+        small_height,
+        imageops::FilterType::Triangle,
+    );
+    // Ink is thin, so the brightest pixels around are the paper.
+    let background = imageops::blur(&dilate(&small, 2), 2.0);
+    let background = imageops::resize(&background, width, height, imageops::FilterType::Triangle);
+
+    let mut out = GrayImage::new(width, height);
+    let mut histogram = [0u64; 256];
+    for (x, y, p) in gray.enumerate_pixels() {
+        let bg = background.get_pixel(x, y).0[0].max(1) as u32;
+        let v = (p.0[0] as u32 * 255 / bg).min(255) as u8;
+        histogram[v as usize] += 1;
+        out.put_pixel(x, y, Luma([v]));
+    }
+
+    // The darkest ink becomes black and what is almost as bright as the
+    // paper becomes white.
+    let total: u64 = histogram.iter().sum();
+    let mut seen = 0;
+    let mut black = 0;
+    for (v, &count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen * 200 > total {
+            black = v as u32;
+            break;
+        }
+    }
+    let white = 235u32.max(black + 1);
+    for p in out.pixels_mut() {
+        let v = p.0[0] as u32;
+        p.0[0] = (v.saturating_sub(black) * 255 / (white - black)).min(255) as u8;
+    }
+    out
+}
+
+/// Replaces every pixel by the brightest pixel within `radius`.
+fn dilate(gray: &GrayImage, radius: u32) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    GrayImage::from_fn(width, height, |x, y| {
+        let mut max = 0;
+        for ny in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+            for nx in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                max = max.max(gray.get_pixel(nx, ny).0[0]);
+            }
+        }
+        Luma([max])
+    })
+}
+
+/// Finds the angle in degrees, clockwise, by which the lines of text are
+/// rotated. The lines are straight when the ink projected on the vertical
+/// axis is the most concentrated.
+fn estimate_skew(gray: &GrayImage) -> Option<f32> {
+    let (width, height) = gray.dimensions();
+    let scale = (DESKEW_SIZE as f32 / width.max(height) as f32).min(1.0);
+    let small = imageops::resize(
+        gray,
+        ((width as f32 * scale) as u32).max(1),
+        ((height as f32 * scale) as u32).max(1),
+        imageops::FilterType::Triangle,
+    );
+    let ink: Vec<(f32, f32)> = small
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p.0[0] < INK_THRESHOLD)
+        .map(|(x, y, _)| (x as f32, y as f32))
+        .collect();
+    // Too little ink to tell anything.
+    if ink.len() < 100 {
+        return None;
+    }
+
+    let diagonal = (small.width() as f32).hypot(small.height() as f32) as usize;
+    let score = |degrees: f32| -> u64 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let mut rows = vec![0u64; 2 * diagonal + 1];
+        for &(x, y) in &ink {
+            let row = (y * cos - x * sin) as isize + diagonal as isize;
+            rows[row.clamp(0, 2 * diagonal as isize) as usize] += 1;
+        }
+        rows.iter().map(|&n| n * n).sum()
+    };
+    let steps = (MAX_SKEW_DEGREES / SKEW_STEP_DEGREES) as i32;
+    let (best, best_score) = (-steps..=steps)
+        .map(|i| i as f32 * SKEW_STEP_DEGREES)
+        .map(|degrees| (degrees, score(degrees)))
+        .max_by_key(|&(_, s)| s)?;
+    // Do not resample the picture for nothing.
+    if best == 0.0 || best_score <= score(0.0) {
+        return None;
+    }
+    Some(best)
+}
+
+/// Rotates a picture clockwise by an angle in degrees, keeping its size and
+/// filling the corners with white.
+fn rotate(gray: &GrayImage, degrees: f32) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    GrayImage::from_fn(width, height, |x, y| {
+        // Where the pixel comes from in the original picture.
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let sx = dx * cos + dy * sin + cx - 0.5;
+        let sy = -dx * sin + dy * cos + cy - 0.5;
+        if sx < 0.0 || sy < 0.0 || sx > (width - 1) as f32 || sy > (height - 1) as f32 {
+            return Luma([255]);
+        }
+        let (x0, y0) = (sx as u32, sy as u32);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+        let p = |x, y| gray.get_pixel(x, y).0[0] as f32;
+        let top = p(x0, y0) * (1.0 - fx) + p(x1, y0) * fx;
+        let bottom = p(x0, y1) * (1.0 - fx) + p(x1, y1) * fx;
+        Luma([(top * (1.0 - fy) + bottom * fy).round() as u8])
+    })
+}
+
+/// Crops the blank margins around the content, leaving a small margin.
+fn crop_margins(gray: GrayImage) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let mut rows = vec![0u32; height as usize];
+    let mut columns = vec![0u32; width as usize];
+    for (x, y, p) in gray.enumerate_pixels() {
+        if p.0[0] < INK_THRESHOLD {
+            rows[y as usize] += 1;
+            columns[x as usize] += 1;
+        }
+    }
+    // A few dark pixels are only noise or dust.
+    let bounds = |counts: &[u32], len: u32| -> Option<(u32, u32)> {
+        let min = (len / 500).max(1);
+        let first = counts.iter().position(|&n| n >= min)? as u32;
+        let last = counts.iter().rposition(|&n| n >= min)? as u32;
+        Some((first, last))
+    };
+    let (top, bottom) = match bounds(&rows, width) {
+        Some(val) => val,
+        None => return gray,
+    };
+    let (left, right) = match bounds(&columns, height) {
+        Some(val) => val,
+        None => return gray,
+    };
+
+    let margin = (width.max(height) as f32 * CROP_MARGIN) as u32;
+    let left = left.saturating_sub(margin);
+    let top = top.saturating_sub(margin);
+    let right = (right + margin).min(width - 1);
+    let bottom = (bottom + margin).min(height - 1);
+    gray.view(left, top, right - left + 1, bottom - top + 1)
+        .to_image()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A white page with lines of text in the middle, and a shadow that
+    /// darkens it towards the right.
+    fn page() -> GrayImage {
+        GrayImage::from_fn(600, 800, |x, y| {
+            let paper = (230 - x * 100 / 600) as u8;
+            let in_text = (150..450).contains(&x) && (200..600).contains(&y);
+            let on_line = y % 40 < 6 && x % 12 < 9;
+            if in_text && on_line {
+                Luma([paper / 5])
+            } else {
+                Luma([paper])
+            }
+        })
+    }
+
+    #[test]
+    fn removes_shadows() {
+        let normalized = normalize(&page());
+        assert_eq!(normalized.get_pixel(100, 100).0[0], 255);
+        assert_eq!(normalized.get_pixel(500, 100).0[0], 255);
+        assert!(normalized.get_pixel(200, 200).0[0] < 60);
+        assert!(normalized.get_pixel(400, 200).0[0] < 60);
+    }
+
+    #[test]
+    fn straightens_text() {
+        let page = normalize(&page());
+        assert_eq!(estimate_skew(&page), None);
+        let skewed = rotate(&page, 3.0);
+        let angle = estimate_skew(&skewed).unwrap();
+        assert!((angle - 3.0).abs() <= SKEW_STEP_DEGREES, "{}", angle);
+    }
+
+    #[test]
+    fn crops_margins() {
+        let cropped = crop_margins(normalize(&page()));
+        let margin = (800.0 * CROP_MARGIN) as u32;
+        assert_eq!(cropped.dimensions(), (300 + 2 * margin, 366 + 2 * margin));
+    }
+}
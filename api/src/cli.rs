@@ -0,0 +1,218 @@
+use std::error::Error;
+use std::fs;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::audit;
+use crate::config::Config;
+use crate::corrections::{self, GcError};
+use crate::passwords;
+use crate::roster;
+use crate::sessions::{self, Role};
+
+const USAGE: &str = "usage: td-api-mpsi1-fr [reset-password <username> | revoke-sessions <username> | set-role <username> student|teacher | import-students <roster.csv> | collect-corrections-garbage [--dry-run] [--force] | generate-correction-variants]";
+
+/// Runs an administration command given on the command line instead of
+/// starting the HTTP server.
+pub(crate) fn run(
+    db: &Connection,
+    config: &Config,
+    args: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match args {
+        [cmd, username] if cmd == "reset-password" => reset_password(db, username),
+        [cmd, username] if cmd == "revoke-sessions" => revoke_sessions(db, username),
+        [cmd, username, role] if cmd == "set-role" => {
+            let role = match role.as_str() {
+                "student" => Role::Student,
+                "teacher" => Role::Teacher,
+                _ => return Err(USAGE.into()),
+            };
+            set_role(db, username, role)
+        }
+        [cmd, path] if cmd == "import-students" => import_students(db, path),
+        [cmd, flags @ ..] if cmd == "collect-corrections-garbage" => {
+            let mut options = corrections::GcOptions::default();
+            for flag in flags {
+                match flag.as_str() {
+                    "--dry-run" => options.dry_run = true,
+                    "--force" => options.force = true,
+                    _ => return Err(USAGE.into()),
+                }
+            }
+            collect_corrections_garbage(db, config, options)
+        }
+        [cmd] if cmd == "generate-correction-variants" => generate_correction_variants(config),
+        _ => Err(USAGE.into()),
+    }
+}
+
+fn find_student(db: &Connection, username: &str) -> Result<u32, Box<dyn Error + Send + Sync>> {
+    db.query_row(
+        "SELECT id FROM students WHERE username = ?",
+        params![username],
+        |r| r.get(0),
+    )
+    .optional()?
+    .ok_or_else(|| format!("no student with username {}", username).into())
+}
+
+/// Forgets the password chosen by a student so that they can log in with the
+/// shared password again, and prints the setup code with which they can choose
+/// a new one.
+fn reset_password(db: &Connection, username: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let id = find_student(db, username)?;
+    db.execute(
+        "UPDATE students SET password_hash = NULL WHERE id = ?",
+        params![id],
+    )?;
+    let setup_code = passwords::generate_setup_code();
+    let expires_at = passwords::save_setup_code(db, id, &setup_code);
+    sessions::revoke_all_for_student(db, id, None);
+    audit::record(
+        db,
+        None,
+        None,
+        audit::Event::new(audit::Action::ResetPassword)
+            .new_value(&serde_json::json!({ "username": username })),
+    );
+    eprintln!(
+        "the password of {} was reset, their setup code is {} until {}",
+        username, setup_code.code, expires_at
+    );
+    Ok(())
+}
+
+/// Logs a student out everywhere.
+fn revoke_sessions(db: &Connection, username: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let id = find_student(db, username)?;
+    sessions::revoke_all_for_student(db, id, None);
+    audit::record(
+        db,
+        None,
+        None,
+        audit::Event::new(audit::Action::RevokeSessions)
+            .new_value(&serde_json::json!({ "username": username })),
+    );
+    eprintln!("the sessions of {} were revoked", username);
+    Ok(())
+}
+
+/// Gives a student the teacher role, or takes it back. The sessions of the
+/// student are revoked since their tokens carry the old role.
+fn set_role(
+    db: &Connection,
+    username: &str,
+    role: Role,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let id = find_student(db, username)?;
+    let old_role: Role =
+        db.query_row("SELECT role FROM students WHERE id = ?", params![id], |r| {
+            r.get(0)
+        })?;
+    db.execute(
+        "UPDATE students SET role = ? WHERE id = ?",
+        params![role, id],
+    )?;
+    sessions::revoke_all_for_student(db, id, None);
+    audit::record(
+        db,
+        None,
+        None,
+        audit::Event::new(audit::Action::SetRole)
+            .old_value(&serde_json::json!({ "username": username, "role": old_role }))
+            .new_value(&serde_json::json!({ "username": username, "role": role })),
+    );
+    eprintln!("the role of {} is now {:?}", username, role);
+    Ok(())
+}
+
+/// Imports the roster of the class from a CSV file, see `roster::import_csv`.
+fn import_students(db: &Connection, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let data = fs::read(path)?;
+    match roster::import_csv(db, &data) {
+        Ok(summary) => {
+            audit::record(
+                db,
+                None,
+                None,
+                audit::Event::new(audit::Action::ImportStudents).new_value(&summary),
+            );
+            eprintln!(
+                "{} students created, {} students updated",
+                summary.created, summary.updated
+            );
+            Ok(())
+        }
+        Err(errors) => {
+            for err in &errors {
+                eprintln!("{}:{}: {}", path, err.line, err.message);
+            }
+            Err(format!("{} invalid lines, nothing was imported", errors.len()).into())
+        }
+    }
+}
+
+/// Deletes the correction pictures that are not used anymore and reports the
+/// ones that are missing, see `corrections::remove_unreferenced_files`.
+fn collect_corrections_garbage(
+    db: &Connection,
+    config: &Config,
+    options: corrections::GcOptions,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // The server may be storing a picture whose correction is not in the
+    // database yet, and only it knows.
+    let _lock = match corrections::lock_directory(&config.corrections_path)? {
+        Some(val) => val,
+        None => {
+            return Err(
+                "the server is running, ask it to collect the garbage with POST /corrections/gc instead"
+                    .into(),
+            )
+        }
+    };
+    let referenced =
+        corrections::purge_trash(db, config.corrections_trash_retention, options.dry_run);
+    let report =
+        match corrections::remove_unreferenced_files(&config.corrections_path, referenced, options) {
+            Ok(val) => val,
+            Err(GcError::Io(err)) => return Err(err.into()),
+            Err(GcError::NoCorrections { pictures }) => {
+                return Err(format!(
+                    "there are {} pictures but no correction, is DB_PATH right? Use --force to delete them anyway",
+                    pictures
+                )
+                .into())
+            }
+            Err(GcError::TooManyUnreferenced {
+                unreferenced,
+                pictures,
+            }) => {
+                return Err(format!(
+                    "{} of the {} pictures are not used, is DB_PATH right? Use --force to delete them anyway",
+                    unreferenced, pictures
+                )
+                .into())
+            }
+        };
+    corrections::log_gc_report(&report);
+    eprintln!(
+        "{} unused pictures {}, {} pictures missing",
+        report.deleted_files.len(),
+        if options.dry_run {
+            "would be deleted"
+        } else {
+            "deleted"
+        },
+        report.missing_files.len()
+    );
+    Ok(())
+}
+
+/// Generates the downscaled variants of the pictures that were uploaded before
+/// they existed.
+fn generate_correction_variants(config: &Config) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let count = corrections::generate_missing_variants(&config.corrections_path)?;
+    eprintln!("variants generated for {} pictures", count);
+    Ok(())
+}
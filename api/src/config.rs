@@ -2,12 +2,16 @@ use std::env;
 use std::env::VarError;
 use std::io;
 use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono_tz::Tz;
 
 pub(crate) struct Config {
     /// The port number on which the HTTP server is listening.
     pub port: u16,
 
-    /// The password used to log in.
+    /// The shared password used to log in by the students who did not choose
+    /// their own password yet.
     pub password: String,
 
     /// The path to the Sqlite database.
@@ -17,10 +21,33 @@ pub(crate) struct Config {
     /// exercise are stored.
     pub corrections_path: PathBuf,
 
+    /// How often the correction pictures that are not used anymore are
+    /// deleted, if they are deleted periodically at all. This is off unless
+    /// asked for, since a wrong database path would make every picture look
+    /// unused.
+    pub corrections_gc_interval: Option<Duration>,
+
+    /// How long deleted corrections can be restored before they are purged.
+    pub corrections_trash_retention: Duration,
+
+    /// The time zone of the school, in which the deadlines of the units are
+    /// given.
+    pub deadline_time_zone: Tz,
+
+    /// How long before the end of the correction day of their group the
+    /// students cannot change the state of their exercises anymore.
+    pub reservation_lock_cutoff: Duration,
+
+    /// The highest grade that teachers can give to a presentation.
+    pub max_grade: u32,
+
     /// A secret value that is used to validate the authenticity of the
     /// log in token.
     pub secret: Vec<u8>,
 
+    /// How long a log in token stays valid.
+    pub session_lifetime: Duration,
+
     /// The header which contains the client's real IP (in case we are serving
     /// requests through a proxy).
     pub real_ip_header: Option<String>,
@@ -60,15 +87,74 @@ impl Config {
         let password = env_var("APP_PASSWD")?;
         let db_path = env_var("DB_PATH")?;
         let corrections_path = env_var("CORRECTIONS_PATH")?;
+        let corrections_gc_interval_hours: u64 = match env_var_opt("CORRECTIONS_GC_INTERVAL_HOURS")?
+        {
+            Some(h) => h
+                .parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            None => 0,
+        };
+        let corrections_trash_retention_days: u64 =
+            match env_var_opt("CORRECTIONS_TRASH_RETENTION_DAYS")? {
+                Some(d) => d
+                    .parse()
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                None => 30,
+            };
+        let deadline_time_zone = match env_var_opt("DEADLINE_TIME_ZONE")? {
+            Some(tz) => tz.parse().map_err(|_err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "DEADLINE_TIME_ZONE must be a time zone name such as Europe/Paris",
+                )
+            })?,
+            None => chrono_tz::Europe::Paris,
+        };
+        let reservation_lock_cutoff_hours: u64 = match env_var_opt("RESERVATION_LOCK_CUTOFF_HOURS")?
+        {
+            Some(h) => h
+                .parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            None => 0,
+        };
+        let max_grade: u32 = match env_var_opt("MAX_GRADE")? {
+            Some(g) => g
+                .parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            None => 20,
+        };
+        if max_grade == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "MAX_GRADE must be positive",
+            ));
+        }
         let secret = base64::decode(env_var("APP_SECRET")?)
             .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let session_lifetime_days: u64 = match env_var_opt("SESSION_LIFETIME_DAYS")? {
+            Some(d) => d
+                .parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            None => 30,
+        };
         let real_ip_header = env_var_opt("REAL_IP_HEADER")?;
         Ok(Config {
             port,
             password,
             db_path: db_path.into(),
             corrections_path: corrections_path.into(),
+            corrections_gc_interval: match corrections_gc_interval_hours {
+                0 => None,
+                h => Some(Duration::from_secs(h * 60 * 60)),
+            },
+            corrections_trash_retention: Duration::from_secs(
+                corrections_trash_retention_days * 24 * 60 * 60,
+            ),
+            deadline_time_zone,
+            reservation_lock_cutoff: Duration::from_secs(reservation_lock_cutoff_hours * 60 * 60),
+            max_grade,
             secret,
+            session_lifetime: Duration::from_secs(session_lifetime_days * 24 * 60 * 60),
             real_ip_header,
         })
     }
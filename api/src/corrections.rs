@@ -0,0 +1,554 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use fs2::FileExt;
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageOutputFormat, ImageResult};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::metadata;
+
+/// The widths of the downscaled variants that are generated for every
+/// picture, so that small screens do not have to load the full resolution.
+pub(crate) const VARIANT_WIDTHS: [u32; 2] = [320, 1280];
+
+const VARIANT_JPEG_QUALITY: u8 = 80;
+
+/// Checks that a string is a SHA-256 digest in URL safe base64 without
+/// padding, so that it can safely be used as a file name.
+pub(crate) fn is_valid_digest(digest: &str) -> bool {
+    digest.len() == 43
+        && digest
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// The name of the file that contains the picture with the given digest.
+pub(crate) fn picture_file_name(digest: &str) -> String {
+    format!("{}.png", digest)
+}
+
+/// The name of the file that contains the variant of the picture with the
+/// given digest that is `width` pixels wide.
+pub(crate) fn variant_file_name(digest: &str, width: u32) -> String {
+    format!("{}-{}.jpg", digest, width)
+}
+
+/// Returns the digest of the picture that a file belongs to, if the file is
+/// a picture or one of its variants.
+fn digest_of_file_name(file_name: &str) -> Option<&str> {
+    let digest = if let Some(stem) = file_name.strip_suffix(".png") {
+        stem
+    } else {
+        let stem = file_name.strip_suffix(".jpg")?;
+        let dash = stem.rfind('-')?;
+        let width: u32 = stem[(dash + 1)..].parse().ok()?;
+        if !VARIANT_WIDTHS.contains(&width) {
+            return None;
+        }
+        &stem[..dash]
+    };
+    if is_valid_digest(digest) {
+        Some(digest)
+    } else {
+        None
+    }
+}
+
+/// Encodes the downscaled variants of a picture as JPEG, along with their
+/// width. Pictures are never upscaled, so a variant of a small picture has
+/// the size of the original.
+pub(crate) fn encode_variants(image: &DynamicImage) -> ImageResult<Vec<(u32, Vec<u8>)>> {
+    // JPEG has no alpha channel.
+    let image = DynamicImage::ImageRgb8(image.to_rgb8());
+    let mut variants = Vec::with_capacity(VARIANT_WIDTHS.len());
+    for &width in VARIANT_WIDTHS.iter() {
+        let mut jpeg = Vec::new();
+        if image.width() > width {
+            image
+                .resize(width, u32::MAX, FilterType::Triangle)
+                .write_to(&mut jpeg, ImageOutputFormat::Jpeg(VARIANT_JPEG_QUALITY))?;
+        } else {
+            image.write_to(&mut jpeg, ImageOutputFormat::Jpeg(VARIANT_JPEG_QUALITY))?;
+        }
+        // The encoder does not write metadata that matters, so a file that
+        // cannot be stripped is better kept as is than lost.
+        let stripped = metadata::strip_jpeg(&jpeg);
+        variants.push((width, if stripped.is_empty() { jpeg } else { stripped }));
+    }
+    Ok(variants)
+}
+
+/// Generates the variants of the pictures that do not have them, such as the
+/// ones uploaded before variants existed, and returns how many pictures were
+/// processed.
+pub(crate) fn generate_missing_variants(corrections_path: &Path) -> io::Result<usize> {
+    let mut count = 0;
+    for entry in fs::read_dir(corrections_path)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let digest = match file_name.to_str().and_then(|n| n.strip_suffix(".png")) {
+            Some(val) if is_valid_digest(val) => val,
+            _ => continue,
+        };
+        let complete = VARIANT_WIDTHS
+            .iter()
+            .all(|&w| corrections_path.join(variant_file_name(digest, w)).exists());
+        if complete {
+            continue;
+        }
+        let image = image::open(entry.path())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let variants = encode_variants(&image)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        for (width, jpeg) in variants {
+            fs::write(
+                corrections_path.join(variant_file_name(digest, width)),
+                jpeg,
+            )?;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Pictures whose perceptual hashes differ by at most this many bits are
+/// considered to be the same.
+const MAX_SIMILAR_DISTANCE: u32 = 4;
+
+/// Computes the difference hash of a picture: each bit tells whether a pixel
+/// of a tiny grayscale version of the picture is brighter than the next one.
+/// Unlike a digest, it barely changes when the picture is re-encoded, resized
+/// or taken again.
+pub(crate) fn perceptual_hash(image: &DynamicImage) -> i64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).into_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0];
+            hash = hash << 1 | brighter as u64;
+        }
+    }
+    // Sqlite only has signed integers.
+    hash as i64
+}
+
+/// Finds a correction of an exercise that looks like a picture, apart from the
+/// picture itself.
+pub(crate) fn find_similar(
+    db: &Connection,
+    unit_id: u32,
+    exercise_index: u32,
+    digest: &str,
+    hash: i64,
+) -> Option<String> {
+    let mut stmt = db
+        .prepare("SELECT picture_digest, perceptual_hash FROM exercise_corrections WHERE unit_id = ? AND unit_exercise = ? AND picture_digest != ? AND perceptual_hash IS NOT NULL AND deleted_at IS NULL")
+        .unwrap();
+    let mut rows = stmt
+        .query_map(params![unit_id, exercise_index, digest], |r| {
+            Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)?))
+        })
+        .unwrap()
+        .map(|r| r.unwrap());
+    rows.find(|(_, other)| (hash ^ other).count_ones() <= MAX_SIMILAR_DISTANCE)
+        .map(|(digest, _)| digest)
+}
+
+/// Whether a picture is used by a correction that is not in the trash.
+pub(crate) fn is_in_use(db: &Connection, digest: &str) -> bool {
+    db.query_row(
+        "SELECT EXISTS (SELECT 1 FROM exercise_corrections WHERE picture_digest = ? AND deleted_at IS NULL)",
+        params![digest],
+        |r| r.get(0),
+    )
+    .unwrap()
+}
+
+/// The file of the pictures directory that the server keeps locked while it
+/// runs, so that the garbage collection is not run from the command line
+/// meanwhile. It would not know which pictures the server is storing.
+const LOCK_FILE_NAME: &str = ".lock";
+
+/// Locks the pictures directory for as long as the returned file is open, or
+/// returns `None` if another process holds the lock.
+pub(crate) fn lock_directory(corrections_path: &Path) -> io::Result<Option<File>> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(corrections_path.join(LOCK_FILE_NAME))?;
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(Some(file)),
+        Err(err) if err.kind() == fs2::lock_contended_error().kind() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Unless forced, the garbage collection refuses to delete more than this
+/// many pictures when they are more than half of them, since this rather
+/// means that the database is not the right one.
+const MAX_UNREFERENCED_PICTURES: usize = 20;
+
+/// How a garbage collection runs.
+#[derive(Clone, Copy, Default, Deserialize)]
+pub(crate) struct GcOptions {
+    /// Only report what would be purged and deleted.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Delete the unused pictures even if there are suspiciously many.
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct GcReport {
+    /// Whether nothing was actually purged nor deleted.
+    #[serde(rename = "dryRun")]
+    pub dry_run: bool,
+    /// The count of deleted corrections that were purged from the trash.
+    #[serde(rename = "purgedCorrections")]
+    pub purged_corrections: usize,
+    /// The digests of the pictures that were deleted because no correction
+    /// uses them anymore.
+    #[serde(rename = "deletedFiles")]
+    pub deleted_files: Vec<String>,
+    /// The digests of the pictures that are used by a correction but that do
+    /// not exist on disk.
+    #[serde(rename = "missingFiles")]
+    pub missing_files: Vec<String>,
+}
+
+#[derive(Debug)]
+pub(crate) enum GcError {
+    Io(io::Error),
+    /// There are pictures but no correction at all, which usually means that
+    /// the database was just created because its path is wrong.
+    NoCorrections {
+        pictures: usize,
+    },
+    /// More than half of the pictures are not used, see
+    /// `MAX_UNREFERENCED_PICTURES`.
+    TooManyUnreferenced {
+        unreferenced: usize,
+        pictures: usize,
+    },
+}
+
+impl From<io::Error> for GcError {
+    fn from(err: io::Error) -> Self {
+        GcError::Io(err)
+    }
+}
+
+/// The pictures that the corrections use after the trash was purged.
+pub(crate) struct ReferencedPictures {
+    purged_corrections: usize,
+    /// The count of corrections left, in the trash or not.
+    corrections: usize,
+    digests: HashSet<String>,
+}
+
+/// Purges the corrections that were deleted for longer than `trash_retention`
+/// and returns the pictures that the remaining ones use. In a dry run, the
+/// corrections are only counted.
+pub(crate) fn purge_trash(
+    db: &Connection,
+    trash_retention: Duration,
+    dry_run: bool,
+) -> ReferencedPictures {
+    const EXPIRED: &str = "deleted_at IS NOT NULL AND deleted_at < datetime('now', ?1)";
+    let age = sqlite_age(trash_retention);
+    let purged_corrections = if dry_run {
+        db.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM exercise_corrections WHERE {}",
+                EXPIRED
+            ),
+            params![age],
+            |r| r.get::<_, i64>(0),
+        )
+        .unwrap() as usize
+    } else {
+        db.execute(
+            &format!("DELETE FROM exercise_corrections WHERE {}", EXPIRED),
+            params![age],
+        )
+        .unwrap()
+    };
+
+    let mut stmt = db
+        .prepare(&format!(
+            "SELECT picture_digest FROM exercise_corrections WHERE NOT ({})",
+            EXPIRED
+        ))
+        .unwrap();
+    let rows: Vec<String> = stmt
+        .query_map(params![age], |r| r.get(0))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    ReferencedPictures {
+        purged_corrections,
+        corrections: rows.len(),
+        digests: rows.into_iter().collect(),
+    }
+}
+
+/// Reconciles the pictures on disk with the ones that the corrections use.
+///
+/// Pictures that no correction references are deleted along with their
+/// variants, and corrections whose picture is missing are reported. A picture
+/// is shared by all the corrections with the same digest, so it is kept as
+/// long as one of them exists, even in the trash. Files that are not named
+/// like pictures are left alone, and files that cannot be deleted are logged
+/// and skipped.
+///
+/// Nothing is deleted if the database looks like the wrong one, unless the
+/// collection is forced, see `GcError`.
+///
+/// No picture may be stored while this runs, otherwise a picture that was
+/// just uploaded could be deleted: callers hold the pictures lock, see
+/// `collect_garbage`.
+pub(crate) fn remove_unreferenced_files(
+    corrections_path: &Path,
+    referenced: ReferencedPictures,
+    options: GcOptions,
+) -> Result<GcReport, GcError> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(corrections_path)? {
+        let entry = match entry {
+            Ok(val) => val,
+            Err(err) => {
+                eprintln!("failed to read correction pictures directory: {:?}", err);
+                continue;
+            }
+        };
+        let file_name = match entry.file_name().into_string() {
+            Ok(val) => val,
+            Err(_) => continue,
+        };
+        let digest = match digest_of_file_name(&file_name) {
+            Some(val) => val.to_owned(),
+            None => continue,
+        };
+        files.push((file_name, digest));
+    }
+
+    let found: HashSet<&str> = files
+        .iter()
+        .filter(|(file_name, _)| file_name.ends_with(".png"))
+        .map(|(_, digest)| digest.as_str())
+        .collect();
+    let mut unreferenced: Vec<String> = found
+        .iter()
+        .filter(|&&digest| !referenced.digests.contains(digest))
+        .map(|&digest| digest.to_owned())
+        .collect();
+    unreferenced.sort();
+    if !options.force && !unreferenced.is_empty() {
+        if referenced.corrections == 0 {
+            return Err(GcError::NoCorrections {
+                pictures: found.len(),
+            });
+        }
+        if unreferenced.len() > MAX_UNREFERENCED_PICTURES && unreferenced.len() * 2 > found.len() {
+            return Err(GcError::TooManyUnreferenced {
+                unreferenced: unreferenced.len(),
+                pictures: found.len(),
+            });
+        }
+    }
+
+    // A missing variant is not an issue since the original is served
+    // instead, but a missing original is.
+    let mut missing_files: Vec<String> = referenced
+        .digests
+        .iter()
+        .filter(|&digest| !found.contains(digest.as_str()))
+        .cloned()
+        .collect();
+    missing_files.sort();
+
+    let mut report = GcReport {
+        dry_run: options.dry_run,
+        purged_corrections: referenced.purged_corrections,
+        deleted_files: Vec::new(),
+        missing_files,
+    };
+    if options.dry_run {
+        report.deleted_files = unreferenced;
+        return Ok(report);
+    }
+
+    let mut failed = HashSet::new();
+    for (file_name, digest) in &files {
+        if referenced.digests.contains(digest) {
+            continue;
+        }
+        if let Err(err) = fs::remove_file(corrections_path.join(file_name)) {
+            eprintln!(
+                "failed to delete unused correction picture file {}: {:?}",
+                file_name, err
+            );
+            if file_name.ends_with(".png") {
+                failed.insert(digest.as_str());
+            }
+        }
+    }
+    report.deleted_files = unreferenced
+        .into_iter()
+        .filter(|digest| !failed.contains(digest.as_str()))
+        .collect();
+
+    Ok(report)
+}
+
+/// Purges the trash and deletes the pictures that are not used anymore, see
+/// `remove_unreferenced_files`.
+///
+/// The database is only locked while it is read, and the files are handled on
+/// a blocking thread so that the server keeps answering meanwhile.
+pub(crate) async fn collect_garbage(
+    db: &Mutex<Connection>,
+    pictures: &Mutex<()>,
+    corrections_path: &Path,
+    trash_retention: Duration,
+    options: GcOptions,
+) -> Result<GcReport, GcError> {
+    let _pictures = pictures.lock().await;
+    let referenced = purge_trash(&*db.lock().await, trash_retention, options.dry_run);
+    let corrections_path = corrections_path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        remove_unreferenced_files(&corrections_path, referenced, options)
+    })
+    .await
+    .map_err(|err| GcError::Io(io::Error::other(err)))?
+}
+
+/// Formats a duration as a Sqlite date modifier that goes back in time by
+/// that duration.
+pub(crate) fn sqlite_age(duration: Duration) -> String {
+    format!("-{} seconds", duration.as_secs())
+}
+
+/// Logs the outcome of a garbage collection.
+pub(crate) fn log_gc_report(report: &GcReport) {
+    let prefix = if report.dry_run {
+        "dry run: would have "
+    } else {
+        ""
+    };
+    if report.purged_corrections > 0 {
+        eprintln!(
+            "{}purged {} corrections from the trash",
+            prefix, report.purged_corrections
+        );
+    }
+    for digest in &report.deleted_files {
+        eprintln!("{}deleted unused correction picture {}", prefix, digest);
+    }
+    for digest in &report.missing_files {
+        eprintln!("correction picture {} is missing", digest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{Rgb, RgbImage};
+
+    fn gradient(flipped: bool) -> DynamicImage {
+        let image = RgbImage::from_fn(400, 300, |x, y| {
+            let v = ((x * 255 / 400) as u8) ^ ((y / 30 * 40) as u8);
+            Rgb([v, v, v])
+        });
+        let image = DynamicImage::ImageRgb8(image);
+        if flipped {
+            image.fliph()
+        } else {
+            image
+        }
+    }
+
+    #[test]
+    fn perceptual_hash_survives_reencoding() {
+        let original = gradient(false);
+        let mut jpeg = Vec::new();
+        original
+            .resize(200, 200, FilterType::Triangle)
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(50))
+            .unwrap();
+        let reencoded = image::load_from_memory(&jpeg).unwrap();
+
+        let hash = perceptual_hash(&original);
+        let distance = |other: &DynamicImage| (hash ^ perceptual_hash(other)).count_ones();
+        assert!(distance(&reencoded) <= MAX_SIMILAR_DISTANCE);
+        assert!(distance(&gradient(true)) > MAX_SIMILAR_DISTANCE);
+    }
+
+    #[test]
+    fn removes_unreferenced_files() {
+        let dir = std::env::temp_dir().join(format!("corrections-gc-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let kept = "k".repeat(43);
+        let unused = "u".repeat(43);
+        let missing = "m".repeat(43);
+        for name in &[
+            picture_file_name(&kept),
+            variant_file_name(&kept, 320),
+            picture_file_name(&unused),
+            variant_file_name(&unused, 1280),
+            "notes.txt".to_owned(),
+        ] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let referenced = || ReferencedPictures {
+            purged_corrections: 0,
+            corrections: 2,
+            digests: vec![kept.clone(), missing.clone()].into_iter().collect(),
+        };
+        let dry_run = GcOptions {
+            dry_run: true,
+            force: false,
+        };
+        let dry_report = remove_unreferenced_files(&dir, referenced(), dry_run).unwrap();
+        assert_eq!(dry_report.deleted_files, vec![unused.clone()]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 5);
+        let no_corrections = ReferencedPictures {
+            purged_corrections: 0,
+            corrections: 0,
+            digests: HashSet::new(),
+        };
+        assert!(matches!(
+            remove_unreferenced_files(&dir, no_corrections, GcOptions::default()),
+            Err(GcError::NoCorrections { pictures: 2 })
+        ));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 5);
+
+        let report = remove_unreferenced_files(&dir, referenced(), GcOptions::default()).unwrap();
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.deleted_files, vec![unused]);
+        assert_eq!(report.missing_files, vec![missing]);
+        assert_eq!(
+            left,
+            vec![
+                variant_file_name(&kept, 320),
+                picture_file_name(&kept),
+                "notes.txt".to_owned(),
+            ]
+        );
+    }
+}
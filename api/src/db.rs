@@ -0,0 +1,98 @@
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use rusqlite::{Connection, NO_PARAMS};
+
+/// The migrations that bring an empty database up to date, in order.
+///
+/// The number of migrations that were applied to a database is stored in its
+/// `user_version` pragma. New migrations must only ever be appended to this
+/// list.
+const MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/0001_initial.sql"),
+    include_str!("../migrations/0002_student_passwords.sql"),
+    include_str!("../migrations/0003_sessions.sql"),
+    include_str!("../migrations/0004_roles.sql"),
+    include_str!("../migrations/0005_student_activation.sql"),
+    include_str!("../migrations/0006_student_emails.sql"),
+    include_str!("../migrations/0007_correction_perceptual_hashes.sql"),
+    include_str!("../migrations/0008_correction_trash.sql"),
+    include_str!("../migrations/0009_audit_log.sql"),
+    include_str!("../migrations/0010_reservation_limits.sql"),
+    include_str!("../migrations/0011_waitlist.sql"),
+    include_str!("../migrations/0012_exercise_state_timestamps.sql"),
+    include_str!("../migrations/0013_presentation_feedback.sql"),
+    include_str!("../migrations/0014_exercise_notices.sql"),
+    include_str!("../migrations/0015_password_setup_codes.sql"),
+    include_str!("../migrations/0016_correction_uploader_nullable.sql"),
+];
+
+#[derive(Debug)]
+pub(crate) enum MigrationError {
+    Sqlite(rusqlite::Error),
+    /// The database was migrated by a newer version of the server.
+    SchemaTooNew {
+        found: u32,
+        supported: u32,
+    },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Sqlite(err) => write!(f, "failed to migrate database: {}", err),
+            MigrationError::SchemaTooNew { found, supported } => write!(
+                f,
+                "database schema version {} is newer than the latest supported version {}",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl Error for MigrationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MigrationError::Sqlite(err) => Some(err),
+            MigrationError::SchemaTooNew { .. } => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(err: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(err)
+    }
+}
+
+/// Opens the Sqlite database and applies the migrations that it is missing.
+pub(crate) fn open(path: &Path) -> Result<Connection, MigrationError> {
+    let mut db = Connection::open(path)?;
+    migrate(&mut db)?;
+    Ok(db)
+}
+
+/// Applies the migrations that were not applied yet to the database.
+///
+/// Each migration runs in its own transaction together with the update of the
+/// schema version, so that a failing migration leaves the database at the
+/// previous version.
+pub(crate) fn migrate(db: &mut Connection) -> Result<(), MigrationError> {
+    let supported = MIGRATIONS.len() as u32;
+    let found: u32 = db.query_row("PRAGMA user_version", NO_PARAMS, |r| r.get(0))?;
+    if found > supported {
+        return Err(MigrationError::SchemaTooNew { found, supported });
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(found as usize) {
+        let version = i as u32 + 1;
+        let tx = db.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", &version)?;
+        tx.commit()?;
+        eprintln!("migrated database to schema version {}", version);
+    }
+
+    Ok(())
+}
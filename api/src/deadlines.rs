@@ -0,0 +1,43 @@
+//! The deadlines of the units, after which the students cannot change the
+//! state of their exercises anymore.
+
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Returns when the changes lock for a group whose correction day is
+/// `deadline`, in the `YYYY-MM-DD` format: `cutoff` before the end of that
+/// day in the time zone of the school, whatever its offset from UTC is on
+/// that day. `None` means that the deadline is invalid, which only happens
+/// with units created by hand.
+pub(crate) fn lock_time(deadline: &str, time_zone: Tz, cutoff: Duration) -> Option<DateTime<Utc>> {
+    let end_of_day = NaiveDate::parse_from_str(deadline, "%Y-%m-%d")
+        .ok()?
+        .succ_opt()?
+        .and_hms_opt(0, 0, 0)?;
+    // Midnight may not exist in time zones that change their offset then.
+    let end_of_day = time_zone.from_local_datetime(&end_of_day).earliest()?;
+    Some(end_of_day.with_timezone(&Utc) - chrono::Duration::from_std(cutoff).ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_before_the_end_of_the_correction_day() {
+        let paris = chrono_tz::Europe::Paris;
+        let cutoff = Duration::from_secs(6 * 60 * 60);
+        assert_eq!(
+            lock_time("2021-03-01", paris, cutoff),
+            Some(Utc.with_ymd_and_hms(2021, 3, 1, 17, 0, 0).unwrap())
+        );
+        // Paris is one hour further from UTC in summer.
+        assert_eq!(
+            lock_time("2021-06-01", paris, cutoff),
+            Some(Utc.with_ymd_and_hms(2021, 6, 1, 16, 0, 0).unwrap())
+        );
+        assert_eq!(lock_time("2021-3-1x", paris, Duration::from_secs(0)), None);
+    }
+}
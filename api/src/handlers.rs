@@ -3,22 +3,52 @@ use std::{
     io::{Cursor, ErrorKind},
 };
 
-use hmac::{Hmac, Mac, NewMac};
+use chrono::{SecondsFormat, Utc};
 use http::StatusCode;
 use hyper::{Body, Request, Response};
-use image::{io::Reader as ImageReader, GenericImageView, ImageFormat, ImageOutputFormat};
+use image::{
+    io::Reader as ImageReader, DynamicImage, GenericImageView, ImageFormat, ImageOutputFormat,
+};
 use rusqlite::Error as SqliteError;
 use rusqlite::ErrorCode as SqliteErrorCode;
-use rusqlite::{params, Connection, NO_PARAMS};
+use rusqlite::{params, Connection, OptionalExtension, Row, NO_PARAMS};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use subtle::ConstantTimeEq;
 use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex};
 
+use crate::audit;
+use crate::cleanup;
 use crate::config::Config;
+use crate::corrections;
+use crate::deadlines;
 use crate::http_helpers::*;
+use crate::metadata;
+use crate::passwords;
+use crate::scanned_pdf::{self, PdfErrorKind};
+use crate::sessions::{self, Role};
+
+mod assignments;
+mod audit_log;
+mod feedback;
+mod notices;
+mod pictures;
+mod stats;
+mod students;
+mod trash;
+mod units;
 
-type HmacSha256 = Hmac<Sha256>;
+pub(crate) use assignments::assign_exercises;
+pub(crate) use audit_log::audit_log;
+pub(crate) use feedback::{set_presentation_feedback, student_feedback};
+pub(crate) use notices::dismiss_notices;
+pub(crate) use pictures::{collect_correction_garbage, correction_picture};
+pub(crate) use stats::{class_stats, student_stats};
+pub(crate) use students::{
+    create_student, delete_student, import_students, issue_password_setup_code, list_students,
+    patch_student,
+};
+pub(crate) use trash::{restore_exercise_correction, trashed_corrections};
+pub(crate) use units::{create_unit, delete_unit, patch_unit};
 
 #[derive(Deserialize)]
 struct LogInRequest {
@@ -59,35 +89,55 @@ pub(crate) async fn log_in(
         }
     };
 
-    let db = db.lock().await;
-    let mut stmt = db
-        .prepare("SELECT id FROM students WHERE username = ? LIMIT 1")
+    let student: Option<(u32, Option<String>, Role)> = db
+        .lock()
+        .await
+        .query_row(
+            "SELECT id, password_hash, role FROM students WHERE username = ? AND active LIMIT 1",
+            params![r.username],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+        )
+        .optional()
         .unwrap();
-    let mut rows = stmt.query(params![r.username]).unwrap();
-    let row = rows.next().unwrap();
-    let id: u32 = match row {
-        Some(row) => row.get(0).unwrap(),
-        None => return empty(StatusCode::UNAUTHORIZED),
+    let (id, password_hash, role) = match student {
+        Some(val) => val,
+        None => {
+            passwords::verify_dummy(&r.password).await;
+            return empty(StatusCode::UNAUTHORIZED);
+        }
     };
 
-    let passwd_ok: bool = r
-        .password
-        .as_bytes()
-        .ct_eq(config.password.as_bytes())
-        .into();
-    if !passwd_ok {
+    if !passwords::verify(&r.password, password_hash.as_deref(), config).await {
         return empty(StatusCode::UNAUTHORIZED);
     }
 
-    let id_str = id.to_string();
-    let mut hmac = HmacSha256::new_varkey(&config.secret).unwrap();
-    hmac.update(id_str.as_bytes());
-    let sig = base64::encode_config(hmac.finalize().into_bytes(), base64::URL_SAFE_NO_PAD);
-    let token = format!("{}.{}", id_str, sig);
+    let token = sessions::create(&*db.lock().await, id, role, config);
 
     json(&token, StatusCode::OK)
 }
 
+pub(crate) async fn log_out(
+    req: Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    let principal = match get_logged_in_principal(&req, db, config).await {
+        Ok(val) => val,
+        Err(err) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!("log out request with invalid authentication: {:?}", err),
+            );
+            return empty(StatusCode::FORBIDDEN);
+        }
+    };
+
+    sessions::revoke(&*db.lock().await, principal.session_id);
+
+    empty(StatusCode::OK)
+}
+
 #[derive(Serialize)]
 struct Student {
     id: u32,
@@ -98,13 +148,20 @@ struct Student {
     in_group_even: bool,
 }
 
+#[derive(Serialize)]
+struct Me {
+    #[serde(flatten)]
+    student: Student,
+    role: Role,
+}
+
 pub(crate) async fn me(
     req: Request<Body>,
     db: &Mutex<Connection>,
     config: &Config,
 ) -> Response<Body> {
-    let student_id = match get_logged_in_user_id(&req, config) {
-        Ok(val) => val,
+    let student_id = match get_logged_in_principal(&req, db, config).await {
+        Ok(val) => val.student_id,
         Err(err) => {
             warn_for_req(
                 &req,
@@ -120,7 +177,7 @@ pub(crate) async fn me(
 
     let db = db.lock().await;
     let mut stmt = db
-        .prepare("SELECT id, username, full_name, in_group_even FROM students WHERE id = ?")
+        .prepare("SELECT id, username, full_name, in_group_even, role FROM students WHERE id = ?")
         .unwrap();
     let mut rows = stmt.query(params![student_id]).unwrap();
     let row = match rows.next().unwrap() {
@@ -128,16 +185,136 @@ pub(crate) async fn me(
         None => return empty(StatusCode::UNAUTHORIZED),
     };
 
-    let me = Student {
-        id: row.get(0).unwrap(),
-        username: row.get(1).unwrap(),
-        full_name: row.get(2).unwrap(),
-        in_group_even: row.get(3).unwrap(),
+    let me = Me {
+        student: Student {
+            id: row.get(0).unwrap(),
+            username: row.get(1).unwrap(),
+            full_name: row.get(2).unwrap(),
+            in_group_even: row.get(3).unwrap(),
+        },
+        role: row.get(4).unwrap(),
     };
 
     json(&me, StatusCode::OK)
 }
 
+#[derive(Deserialize)]
+struct ChangePasswordRequest {
+    #[serde(rename = "currentPassword")]
+    current_password: String,
+    #[serde(rename = "newPassword")]
+    new_password: String,
+}
+
+pub(crate) async fn change_password(
+    mut req: Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    let principal = match get_logged_in_principal(&req, db, config).await {
+        Ok(val) => val,
+        Err(err) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!(
+                    "password change request with invalid authentication: {:?}",
+                    err
+                ),
+            );
+            return empty(StatusCode::FORBIDDEN);
+        }
+    };
+    let student_id = principal.student_id;
+
+    let b = match collect_body(req.body_mut(), 1024).await {
+        Ok(val) => val,
+        Err(CollectBodyError::ReadError(err)) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!(
+                    "failed to read body from password change request: {:?}",
+                    err
+                ),
+            );
+            return empty(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        Err(CollectBodyError::TooLarge) => {
+            warn_for_req(&req, config, "password change request body is too large");
+            return empty(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+    };
+
+    let r: ChangePasswordRequest = match serde_json::from_slice(&b) {
+        Ok(val) => val,
+        Err(err) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!("password change request is invalid: {:?}", err),
+            );
+            return empty(StatusCode::BAD_REQUEST);
+        }
+    };
+    if r.new_password.len() < passwords::MIN_PASSWORD_LEN
+        || r.new_password.len() > passwords::MAX_PASSWORD_LEN
+    {
+        return empty(StatusCode::BAD_REQUEST);
+    }
+
+    let hashes: Option<(Option<String>, Option<String>)> = db
+        .lock()
+        .await
+        .query_row(
+            "SELECT password_hash, CASE WHEN password_setup_code_expires_at > CURRENT_TIMESTAMP THEN password_setup_code_hash END FROM students WHERE id = ?",
+            params![student_id],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()
+        .unwrap();
+    let (password_hash, setup_code_hash) = match hashes {
+        Some(val) => val,
+        None => return empty(StatusCode::UNAUTHORIZED),
+    };
+
+    // The current password is checked again so that a stolen token is not
+    // enough to lock the student out of their account. The shared password
+    // is not enough either, otherwise any classmate could choose the password
+    // of a student who did not yet: the student needs the setup code that a
+    // teacher gave them.
+    let mut allowed = false;
+    if let Some(h) = password_hash {
+        allowed = passwords::verify(&r.current_password, Some(&h), config).await;
+    }
+    if let Some(h) = setup_code_hash.filter(|_| !allowed) {
+        let code = passwords::normalize_setup_code(&r.current_password);
+        allowed = passwords::verify(&code, Some(&h), config).await;
+    }
+    if !allowed {
+        return empty(StatusCode::FORBIDDEN);
+    }
+
+    let new_hash = passwords::hash(&r.new_password).await;
+    let db = db.lock().await;
+    let mut stmt = db
+        .prepare("UPDATE students SET password_hash = ?, password_setup_code_hash = NULL, password_setup_code_expires_at = NULL WHERE id = ?")
+        .unwrap();
+    stmt.execute(params![new_hash, student_id]).unwrap();
+
+    // Someone who knew the old password may still be logged in elsewhere.
+    sessions::revoke_all_for_student(&db, student_id, Some(principal.session_id));
+    audit::record_for_req(
+        &db,
+        &req,
+        config,
+        student_id,
+        audit::Event::new(audit::Action::ChangePassword),
+    );
+
+    empty(StatusCode::OK)
+}
+
 #[derive(Serialize)]
 struct Unit {
     id: u32,
@@ -148,6 +325,44 @@ struct Unit {
     deadline_group_even: String,
     #[serde(rename = "deadlineGroupOdd")]
     deadline_group_odd: String,
+    /// The maximum number of students who can reserve an exercise at once.
+    #[serde(rename = "maxReserversPerExercise")]
+    max_reservers_per_exercise: Option<u32>,
+    /// Whether `max_reservers_per_exercise` applies to each group separately.
+    #[serde(rename = "maxReserversPerGroup")]
+    max_reservers_per_group: bool,
+    /// The maximum number of exercises of the unit that a student can have
+    /// reserved at once.
+    #[serde(rename = "maxReservationsPerStudent")]
+    max_reservations_per_student: Option<u32>,
+}
+
+/// The columns of the `units` table read by `Unit::from_row`.
+const UNIT_COLUMNS: &str = "id, name, exercise_count, deadline_group_even, deadline_group_odd, max_reservers_per_exercise, max_reservers_per_group, max_reservations_per_student";
+
+impl Unit {
+    fn from_row(r: &Row) -> rusqlite::Result<Unit> {
+        Ok(Unit {
+            id: r.get(0)?,
+            name: r.get(1)?,
+            exercise_count: r.get(2)?,
+            deadline_group_even: r.get(3)?,
+            deadline_group_odd: r.get(4)?,
+            max_reservers_per_exercise: r.get(5)?,
+            max_reservers_per_group: r.get(6)?,
+            max_reservations_per_student: r.get(7)?,
+        })
+    }
+}
+
+fn get_unit(db: &Connection, unit_id: u32) -> Option<Unit> {
+    db.query_row(
+        &format!("SELECT {} FROM units WHERE id = ?", UNIT_COLUMNS),
+        params![unit_id],
+        Unit::from_row,
+    )
+    .optional()
+    .unwrap()
 }
 
 pub(crate) async fn units(
@@ -155,7 +370,7 @@ pub(crate) async fn units(
     db: &Mutex<Connection>,
     config: &Config,
 ) -> Response<Body> {
-    if let Err(err) = get_logged_in_user_id(&req, config) {
+    if let Err(err) = get_logged_in_principal(&req, db, config).await {
         warn_for_req(
             &req,
             config,
@@ -171,20 +386,12 @@ pub(crate) async fn units(
 
     let db = db.lock().await;
     let mut stmt = db
-        .prepare(
-            "SELECT id, name, exercise_count, deadline_group_even, deadline_group_odd FROM units",
-        )
+        .prepare(&format!("SELECT {} FROM units", UNIT_COLUMNS))
         .unwrap();
     let mut rows = stmt.query(NO_PARAMS).unwrap();
     let mut row = rows.next().unwrap();
     while let Some(r) = row {
-        result.push(Unit {
-            id: r.get(0).unwrap(),
-            name: r.get(1).unwrap(),
-            exercise_count: r.get(2).unwrap(),
-            deadline_group_even: r.get(3).unwrap(),
-            deadline_group_odd: r.get(4).unwrap(),
-        });
+        result.push(Unit::from_row(r).unwrap());
         row = rows.next().unwrap();
     }
 
@@ -194,9 +401,12 @@ pub(crate) async fn units(
 #[derive(Serialize, Default)]
 struct Exercise {
     #[serde(rename = "reservedBy")]
-    reserved_by: Vec<Student>,
+    reserved_by: Vec<ReservingStudent>,
     #[serde(rename = "presentedBy")]
-    presented_by: Vec<Student>,
+    presented_by: Vec<PresentingStudent>,
+    /// The students waiting for a reservation to be released, in order.
+    #[serde(rename = "waitlistedBy")]
+    waitlisted_by: Vec<WaitlistedStudent>,
     /// For whatever reason, the teacher said that students should not do this
     /// exercise.
     #[serde(rename = "blocked")]
@@ -207,9 +417,58 @@ struct Exercise {
     /// The teacher corrected the exercise for group B
     #[serde(rename = "teacherCorrectedForGroupOdd")]
     teacher_corrected_for_group_odd: bool,
-    /// A list of digests for the pictures with the correction.
+    /// The pictures with the correction, in the order they were uploaded.
     #[serde(rename = "correctionImages")]
-    correction_images: Vec<String>,
+    correction_images: Vec<CorrectionImage>,
+    /// The notices of the student making the request about this exercise.
+    #[serde(rename = "noticesForMe")]
+    notices_for_me: Vec<notices::Notice>,
+}
+
+/// The times are in RFC 3339 format, and missing when unknown.
+#[derive(Serialize)]
+struct ReservingStudent {
+    #[serde(flatten)]
+    student: Student,
+    #[serde(rename = "reservedAt")]
+    reserved_at: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PresentingStudent {
+    #[serde(flatten)]
+    student: Student,
+    /// Missing if the student presented without reserving first.
+    #[serde(rename = "reservedAt")]
+    reserved_at: Option<String>,
+    #[serde(rename = "presentedAt")]
+    presented_at: Option<String>,
+    /// Only sent to teachers and to the student who presented.
+    #[serde(flatten)]
+    feedback: Option<feedback::PresentationFeedback>,
+}
+
+#[derive(Serialize)]
+struct WaitlistedStudent {
+    #[serde(flatten)]
+    student: Student,
+    /// The position of the student in the waitlist, starting at 1.
+    position: u32,
+}
+
+#[derive(Serialize)]
+struct CorrectionImage {
+    digest: String,
+    /// Grants access to the picture for a short time and as long as the
+    /// session is open, to be put in its URL.
+    #[serde(rename = "accessToken")]
+    access_token: String,
+    /// The student is missing if their account was deleted since.
+    #[serde(rename = "createdBy")]
+    created_by: Option<Student>,
+    /// When the picture was uploaded, in RFC 3339 format.
+    #[serde(rename = "createdAt")]
+    created_at: String,
 }
 
 pub(crate) async fn unit_exercises(
@@ -218,36 +477,35 @@ pub(crate) async fn unit_exercises(
     db: &Mutex<Connection>,
     config: &Config,
 ) -> Response<Body> {
-    if let Err(err) = get_logged_in_user_id(&req, config) {
-        warn_for_req(
-            &req,
-            config,
-            &format!(
-                "list unit exercises request with invalid authentication: {:?}",
-                err
-            ),
-        );
-        return empty(StatusCode::FORBIDDEN);
+    let principal = match get_logged_in_principal(&req, db, config).await {
+        Ok(val) => val,
+        Err(err) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!(
+                    "list unit exercises request with invalid authentication: {:?}",
+                    err
+                ),
+            );
+            return empty(StatusCode::FORBIDDEN);
+        }
     };
 
     let db = db.lock().await;
 
-    let exercise_count: u32 = {
-        let mut stmt = db
-            .prepare("SELECT exercise_count FROM units WHERE id = ? LIMIT 1")
-            .unwrap();
-        let mut rows = stmt.query(params![unit_id]).unwrap();
-        let row = match rows.next().unwrap() {
-            Some(val) => val,
-            None => return empty(StatusCode::NOT_FOUND),
-        };
-        row.get(0).unwrap()
+    let unit = match get_unit(&db, unit_id) {
+        Some(val) => val,
+        None => return empty(StatusCode::NOT_FOUND),
     };
 
     let mut result: Vec<Exercise> = Vec::new();
-    result.resize_with(usize::try_from(exercise_count).unwrap(), Default::default);
+    result.resize_with(
+        usize::try_from(unit.exercise_count).unwrap(),
+        Default::default,
+    );
 
-    let mut stmt = db.prepare("SELECT student_id, exercise_index, state, username, full_name, in_group_even FROM exercise_student_state INNER JOIN students ON exercise_student_state.student_id = students.id WHERE unit_id = ?").unwrap();
+    let mut stmt = db.prepare(&format!("SELECT student_id, exercise_index, state, username, full_name, in_group_even, strftime('%Y-%m-%dT%H:%M:%SZ', reserved_at), strftime('%Y-%m-%dT%H:%M:%SZ', presented_at), {} FROM exercise_student_state INNER JOIN students ON exercise_student_state.student_id = students.id WHERE unit_id = ? ORDER BY waitlist_order", feedback::FEEDBACK_COLUMNS)).unwrap();
     let mut rows = stmt.query(params![unit_id]).unwrap();
     let mut row = rows.next().unwrap();
     while let Some(r) = row {
@@ -261,17 +519,49 @@ pub(crate) async fn unit_exercises(
             Some(val) => val,
             None => panic!("Exercise index out of bounds: {}", exercise_idx),
         };
-        let vec = match state {
-            0 => &mut exercise.reserved_by,
-            1 => &mut exercise.presented_by,
-            _ => panic!("Unexpected exercise state: {}", state),
-        };
-        vec.push(Student {
+        let student = Student {
             id: student_id,
             username: student_username,
             full_name: student_full_name,
             in_group_even: student_in_group_even,
-        });
+        };
+        match state {
+            0 => exercise.reserved_by.push(ReservingStudent {
+                student,
+                reserved_at: r.get(6).unwrap(),
+            }),
+            1 => {
+                // The grades are hidden from the other students.
+                let feedback = Some(feedback::PresentationFeedback::from_row(r, 8).unwrap())
+                    .filter(|f| {
+                        !f.is_empty()
+                            && (principal.is_teacher() || principal.student_id == student_id)
+                    });
+                exercise.presented_by.push(PresentingStudent {
+                    student,
+                    reserved_at: r.get(6).unwrap(),
+                    presented_at: r.get(7).unwrap(),
+                    feedback,
+                });
+            }
+            2 => {
+                // The students are promoted in order among those who could
+                // get a reservation, so only their group counts with a limit
+                // per group.
+                let position = 1 + exercise
+                    .waitlisted_by
+                    .iter()
+                    .filter(|w| {
+                        !unit.max_reservers_per_group
+                            || w.student.in_group_even == student_in_group_even
+                    })
+                    .count() as u32;
+                exercise
+                    .waitlisted_by
+                    .push(WaitlistedStudent { student, position });
+            }
+            _ => panic!("Unexpected exercise state: {}", state),
+        }
         row = rows.next().unwrap();
     }
 
@@ -291,21 +581,39 @@ pub(crate) async fn unit_exercises(
     }
 
     let mut stmt = db
-        .prepare("SELECT unit_exercise, picture_digest FROM exercise_corrections WHERE unit_id = ?")
+        .prepare("SELECT unit_exercise, picture_digest, strftime('%Y-%m-%dT%H:%M:%SZ', created_at), students.id, username, full_name, in_group_even FROM exercise_corrections LEFT JOIN students ON exercise_corrections.created_by = students.id WHERE unit_id = ? AND deleted_at IS NULL ORDER BY exercise_corrections.id")
         .unwrap();
     let mut rows = stmt.query(params![unit_id]).unwrap();
     let mut row = rows.next().unwrap();
     while let Some(r) = row {
         let exercise_idx: u32 = r.get(0).unwrap();
-        let digest: String = r.get(1).unwrap();
         let exercise = match result.get_mut(usize::try_from(exercise_idx).unwrap()) {
             Some(val) => val,
             None => panic!("Exercise index out of bounds: {}", exercise_idx),
         };
-        exercise.correction_images.push(digest);
+        let digest: String = r.get(1).unwrap();
+        let created_by_id: Option<u32> = r.get(3).unwrap();
+        exercise.correction_images.push(CorrectionImage {
+            access_token: sessions::picture_token(&digest, principal.session_id, config),
+            digest,
+            created_at: r.get(2).unwrap(),
+            created_by: created_by_id.map(|id| Student {
+                id,
+                username: r.get(4).unwrap(),
+                full_name: r.get(5).unwrap(),
+                in_group_even: r.get(6).unwrap(),
+            }),
+        });
         row = rows.next().unwrap();
     }
 
+    for (exercise_idx, notice) in notices::notices_in_unit(&db, principal.student_id, unit_id) {
+        // The exercise may have been removed from the unit since.
+        if let Some(exercise) = result.get_mut(usize::try_from(exercise_idx).unwrap()) {
+            exercise.notices_for_me.push(notice);
+        }
+    }
+
     json(&result, StatusCode::OK)
 }
 
@@ -317,15 +625,61 @@ enum ExerciseStudentState {
     Reserved,
     #[serde(rename = "presented")]
     Presented,
+    #[serde(rename = "waitlisted")]
+    Waitlisted,
 }
 
 #[derive(Deserialize)]
 struct PatchExerciseRequest {
     #[serde(rename = "stateForMe")]
     state_for_me: Option<ExerciseStudentState>,
+    /// Changes the state of this student instead of the one of the student
+    /// making the request. Only teachers can do this, and they can do it even
+    /// after the deadline.
+    #[serde(rename = "studentId")]
+    student_id: Option<u32>,
     blocked: Option<bool>,
     #[serde(rename = "teacherCorrectedForMyGroup")]
     teacher_corrected_for_my_group: Option<bool>,
+    #[serde(rename = "teacherCorrectedForGroupEven")]
+    teacher_corrected_for_group_even: Option<bool>,
+    #[serde(rename = "teacherCorrectedForGroupOdd")]
+    teacher_corrected_for_group_odd: Option<bool>,
+}
+
+impl PatchExerciseRequest {
+    /// Whether the request changes fields that only teachers can change.
+    fn is_privileged(&self) -> bool {
+        self.student_id.is_some()
+            || self.blocked.is_some()
+            || self.teacher_corrected_for_my_group.is_some()
+            || self.teacher_corrected_for_group_even.is_some()
+            || self.teacher_corrected_for_group_odd.is_some()
+    }
+}
+
+/// The name of the state of a student for an exercise in the audit log.
+fn state_name(state: Option<u32>) -> &'static str {
+    match state {
+        None => "none",
+        Some(0) => "reserved",
+        Some(1) => "presented",
+        Some(2) => "waitlisted",
+        Some(state) => panic!("Unexpected exercise state: {}", state),
+    }
+}
+
+/// Reads one of the boolean columns of the `exercise` table, which has no row
+/// for the exercises whose flags were never set.
+fn exercise_flag(db: &Connection, unit_id: u32, exercise_index: u32, field: &str) -> bool {
+    let query = format!(
+        "SELECT {} FROM exercise WHERE unit_id = ? AND index_ = ?",
+        field
+    );
+    db.query_row(&query, params![unit_id, exercise_index], |r| r.get(0))
+        .optional()
+        .unwrap()
+        .unwrap_or(false)
 }
 
 pub(crate) async fn patch_exercise(
@@ -335,14 +689,14 @@ pub(crate) async fn patch_exercise(
     db: &Mutex<Connection>,
     config: &Config,
 ) -> Response<Body> {
-    let student_id = match get_logged_in_user_id(&req, config) {
+    let principal = match get_logged_in_principal(&req, db, config).await {
         Ok(val) => val,
         Err(err) => {
             warn_for_req(
                 &req,
                 config,
                 &format!(
-                    "exercise correction submission with invalid authentication: {:?}",
+                    "exercise patch request with invalid authentication: {:?}",
                     err
                 ),
             );
@@ -356,12 +710,12 @@ pub(crate) async fn patch_exercise(
             warn_for_req(
                 &req,
                 config,
-                &format!("failed to read body from log in request: {:?}", err),
+                &format!("failed to read body from exercise patch request: {:?}", err),
             );
             return empty(StatusCode::INTERNAL_SERVER_ERROR);
         }
         Err(CollectBodyError::TooLarge) => {
-            warn_for_req(&req, config, "log in request body is too large");
+            warn_for_req(&req, config, "exercise patch request body is too large");
             return empty(StatusCode::PAYLOAD_TOO_LARGE);
         }
     };
@@ -371,55 +725,240 @@ pub(crate) async fn patch_exercise(
             warn_for_req(
                 &req,
                 config,
-                &format!("log in request is invalid: {:?}", err),
+                &format!("exercise patch request is invalid: {:?}", err),
             );
             return empty(StatusCode::BAD_REQUEST);
         }
     };
+    if r.is_privileged() && !principal.is_teacher() {
+        warn_for_req(
+            &req,
+            config,
+            "exercise patch request changing teacher fields from a student",
+        );
+        return empty(StatusCode::FORBIDDEN);
+    }
+    let student_id = principal.student_id;
 
     let db = db.lock().await;
+    // The limits are checked in the same transaction as the changes so that
+    // they hold even if the same exercise is reserved by several students at
+    // once.
+    let tx = db.unchecked_transaction().unwrap();
 
-    let exercise_count: u32 = {
-        let mut stmt = db
-            .prepare("SELECT exercise_count FROM units WHERE id = ? LIMIT 1")
-            .unwrap();
-        let mut rows = stmt.query(params![unit_id]).unwrap();
-        let row = match rows.next().unwrap() {
-            Some(val) => val,
-            None => return empty(StatusCode::NOT_FOUND),
-        };
-        row.get(0).unwrap()
+    let unit = match get_unit(&tx, unit_id) {
+        Some(val) => val,
+        None => return empty(StatusCode::NOT_FOUND),
     };
-    if exercise_index >= exercise_count {
+    if exercise_index >= unit.exercise_count {
         return empty(StatusCode::NOT_FOUND);
     }
 
     if let Some(my_state) = r.state_for_me {
-        let my_state: u32 = match my_state {
-            ExerciseStudentState::None => {
-                let mut stmt = db.prepare("DELETE FROM exercise_student_state WHERE student_id = ? AND unit_id = ? AND exercise_index = ?").unwrap();
-                stmt.execute(params![student_id, unit_id, exercise_index])
-                    .unwrap();
-                return empty(StatusCode::OK);
-            }
-            ExerciseStudentState::Reserved => 0,
-            ExerciseStudentState::Presented => 1,
+        let target_id = r.student_id.unwrap_or(student_id);
+        let in_group_even: bool = match tx
+            .query_row(
+                "SELECT in_group_even FROM students WHERE id = ?",
+                params![target_id],
+                |r| r.get(0),
+            )
+            .optional()
+            .unwrap()
+        {
+            Some(val) => val,
+            None => return empty(StatusCode::NOT_FOUND),
         };
+        if !principal.is_teacher() {
+            if let Err(res) = check_not_locked(&unit, in_group_even, config) {
+                return res;
+            }
+        }
 
-        let mut stmt = db.prepare("INSERT OR REPLACE INTO exercise_student_state (student_id, unit_id, exercise_index, state) VALUES (?, ?, ?, ?)").unwrap();
-        stmt.execute(params![student_id, unit_id, exercise_index, my_state])
+        let old_state: Option<u32> = tx
+            .query_row(
+                "SELECT state FROM exercise_student_state WHERE student_id = ? AND unit_id = ? AND exercise_index = ?",
+                params![target_id, unit_id, exercise_index],
+                |r| r.get(0),
+            )
+            .optional()
             .unwrap();
+        let new_state: Option<u32> = match my_state {
+            ExerciseStudentState::None => None,
+            ExerciseStudentState::Reserved => Some(0),
+            ExerciseStudentState::Presented => Some(1),
+            ExerciseStudentState::Waitlisted => Some(2),
+        };
+        // A presentation with a grade or a comment can only be cancelled by a
+        // teacher, so that a student cannot erase their grade and then
+        // present again.
+        if !principal.is_teacher() && old_state == Some(1) && new_state != Some(1) {
+            let graded: bool = tx
+                .query_row(
+                    "SELECT grade IS NOT NULL OR feedback IS NOT NULL FROM exercise_student_state WHERE student_id = ? AND unit_id = ? AND exercise_index = ?",
+                    params![target_id, unit_id, exercise_index],
+                    |r| r.get(0),
+                )
+                .unwrap();
+            if graded {
+                return json(
+                    &serde_json::json!({ "error": "presentationGraded" }),
+                    StatusCode::CONFLICT,
+                );
+            }
+        }
+        // Presenting an exercise that was not reserved takes a reservation,
+        // which the student could not have made otherwise.
+        let takes_reservation = (new_state == Some(0) && old_state != Some(0))
+            || (new_state == Some(1) && !matches!(old_state, Some(0) | Some(1)));
+        if (takes_reservation || (new_state == Some(2) && old_state != Some(2)))
+            && exercise_flag(&tx, unit_id, exercise_index, "blocked")
+        {
+            return json(
+                &serde_json::json!({ "error": "exerciseBlocked" }),
+                StatusCode::CONFLICT,
+            );
+        }
+        if takes_reservation {
+            if let Err(res) = check_reservation_limits(&tx, &unit, exercise_index, target_id) {
+                return res;
+            }
+        }
+        if new_state == Some(2) && old_state != Some(2) {
+            // Waiting makes no sense when the exercise can be reserved.
+            if exercise_has_room(&tx, &unit, exercise_index, target_id) {
+                return json(
+                    &serde_json::json!({ "error": "exerciseNotFull" }),
+                    StatusCode::CONFLICT,
+                );
+            }
+            if let Err(res) = check_student_reservations(&tx, &unit, exercise_index, target_id) {
+                return res;
+            }
+        }
+
+        if old_state != new_state {
+            match new_state {
+                None => {
+                    tx.execute(
+                        "DELETE FROM exercise_student_state WHERE student_id = ? AND unit_id = ? AND exercise_index = ?",
+                        params![target_id, unit_id, exercise_index],
+                    )
+                    .unwrap();
+                }
+                Some(2) => {
+                    tx.execute(
+                        "INSERT INTO exercise_student_state (student_id, unit_id, exercise_index, state, waitlist_order) VALUES (?, ?, ?, 2, (SELECT COALESCE(MAX(waitlist_order), 0) + 1 FROM exercise_student_state)) \
+                         ON CONFLICT (student_id, unit_id, exercise_index) DO UPDATE SET state = 2, waitlist_order = excluded.waitlist_order, reserved_at = NULL, presented_at = NULL, grade = NULL, max_grade = NULL, feedback = NULL, graded_at = NULL",
+                        params![target_id, unit_id, exercise_index],
+                    )
+                    .unwrap();
+                }
+                Some(0) => {
+                    // Going back from a presentation keeps the reservation
+                    // time.
+                    tx.execute(
+                        "INSERT INTO exercise_student_state (student_id, unit_id, exercise_index, state, reserved_at) VALUES (?, ?, ?, 0, CURRENT_TIMESTAMP) \
+                         ON CONFLICT (student_id, unit_id, exercise_index) DO UPDATE SET state = 0, waitlist_order = NULL, reserved_at = COALESCE(reserved_at, excluded.reserved_at), presented_at = NULL, grade = NULL, max_grade = NULL, feedback = NULL, graded_at = NULL",
+                        params![target_id, unit_id, exercise_index],
+                    )
+                    .unwrap();
+                }
+                Some(state) => {
+                    tx.execute(
+                        "INSERT INTO exercise_student_state (student_id, unit_id, exercise_index, state, presented_at) VALUES (?, ?, ?, ?, CURRENT_TIMESTAMP) \
+                         ON CONFLICT (student_id, unit_id, exercise_index) DO UPDATE SET state = excluded.state, waitlist_order = NULL, presented_at = excluded.presented_at",
+                        params![target_id, unit_id, exercise_index, state],
+                    )
+                    .unwrap();
+                }
+            }
+            audit::record_for_req(
+                &tx,
+                &req,
+                config,
+                student_id,
+                audit::Event::new(audit::Action::SetExerciseState)
+                    .exercise(unit_id, exercise_index)
+                    .old_value(&serde_json::json!({
+                        "studentId": target_id,
+                        "state": state_name(old_state),
+                    }))
+                    .new_value(&serde_json::json!({
+                        "studentId": target_id,
+                        "state": state_name(new_state),
+                    })),
+            );
+            if old_state == Some(0) {
+                promote_waitlisted(&tx, &req, config, student_id, &unit, exercise_index);
+            }
+        }
     }
 
     if let Some(blocked) = r.blocked {
-        let mut stmt = db.prepare("INSERT INTO exercise (unit_id, index_, blocked) VALUES (?, ?, ?) ON CONFLICT (unit_id, index_) DO UPDATE SET blocked = ?").unwrap();
+        let was_blocked = exercise_flag(&tx, unit_id, exercise_index, "blocked");
+        let mut stmt = tx.prepare("INSERT INTO exercise (unit_id, index_, blocked) VALUES (?, ?, ?) ON CONFLICT (unit_id, index_) DO UPDATE SET blocked = ?").unwrap();
         stmt.execute(params![unit_id, exercise_index, blocked, blocked])
             .unwrap();
+        if was_blocked != blocked {
+            audit::record_for_req(
+                &tx,
+                &req,
+                config,
+                student_id,
+                audit::Event::new(audit::Action::SetExerciseBlocked)
+                    .exercise(unit_id, exercise_index)
+                    .old_value(&was_blocked)
+                    .new_value(&blocked),
+            );
+        }
+        if blocked {
+            // Nobody should work on a blocked exercise, so the reservations and
+            // the waitlist are cancelled, and the students are told why.
+            let reservers: Vec<(u32, u32)> = tx
+                .prepare("SELECT student_id, state FROM exercise_student_state WHERE unit_id = ? AND exercise_index = ? AND state IN (0, 2)")
+                .unwrap()
+                .query_map(params![unit_id, exercise_index], |r| {
+                    Ok((r.get(0)?, r.get(1)?))
+                })
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect();
+            tx.execute(
+                "DELETE FROM exercise_student_state WHERE unit_id = ? AND exercise_index = ? AND state IN (0, 2)",
+                params![unit_id, exercise_index],
+            )
+            .unwrap();
+            for (reserver, state) in reservers {
+                let kind = if state == 0 {
+                    "reservationCancelled"
+                } else {
+                    "waitlistCancelled"
+                };
+                notices::add_notice(&tx, reserver, unit_id, exercise_index, kind);
+                audit::record_for_req(
+                    &tx,
+                    &req,
+                    config,
+                    student_id,
+                    audit::Event::new(audit::Action::SetExerciseState)
+                        .exercise(unit_id, exercise_index)
+                        .old_value(&serde_json::json!({
+                            "studentId": reserver,
+                            "state": state_name(Some(state)),
+                        }))
+                        .new_value(&serde_json::json!({
+                            "studentId": reserver,
+                            "state": state_name(None),
+                        })),
+                );
+            }
+        }
     }
 
+    let mut teacher_corrected = Vec::new();
     if let Some(teacher_corrected_for_my_group) = r.teacher_corrected_for_my_group {
         let in_group_even: bool = {
-            let mut stmt = db
+            let mut stmt = tx
                 .prepare("SELECT in_group_even FROM students WHERE id = ?")
                 .unwrap();
             let mut rows = stmt.query(params![student_id]).unwrap();
@@ -430,33 +969,224 @@ pub(crate) async fn patch_exercise(
             row.get(0).unwrap()
         };
 
-        let field = if in_group_even {
-            "teacher_corrected_for_group_even"
+        teacher_corrected.push((in_group_even, teacher_corrected_for_my_group));
+    }
+    if let Some(v) = r.teacher_corrected_for_group_even {
+        teacher_corrected.push((true, v));
+    }
+    if let Some(v) = r.teacher_corrected_for_group_odd {
+        teacher_corrected.push((false, v));
+    }
+
+    for (group_even, value) in teacher_corrected {
+        let (field, action) = if group_even {
+            (
+                "teacher_corrected_for_group_even",
+                audit::Action::SetTeacherCorrectedForGroupEven,
+            )
         } else {
-            "teacher_corrected_for_group_odd"
+            (
+                "teacher_corrected_for_group_odd",
+                audit::Action::SetTeacherCorrectedForGroupOdd,
+            )
         };
+        let old_value = exercise_flag(&tx, unit_id, exercise_index, field);
         let query = format!("INSERT INTO exercise (unit_id, index_, {0}) VALUES (?, ?, ?) ON CONFLICT (unit_id, index_) DO UPDATE SET {0} = ?", field);
-        let mut stmt = db.prepare(&query).unwrap();
-        stmt.execute(params![
-            unit_id,
-            exercise_index,
-            teacher_corrected_for_my_group,
-            teacher_corrected_for_my_group
-        ])
-        .unwrap();
+        let mut stmt = tx.prepare(&query).unwrap();
+        stmt.execute(params![unit_id, exercise_index, value, value])
+            .unwrap();
+        if old_value != value {
+            audit::record_for_req(
+                &tx,
+                &req,
+                config,
+                student_id,
+                audit::Event::new(action)
+                    .exercise(unit_id, exercise_index)
+                    .old_value(&old_value)
+                    .new_value(&value),
+            );
+        }
     }
 
+    tx.commit().unwrap();
+
     empty(StatusCode::OK)
 }
 
+/// Checks that the students of a group can still change the state of the
+/// exercises of a unit, or builds the response telling when they stopped being
+/// able to.
+#[allow(clippy::result_large_err)]
+fn check_not_locked(
+    unit: &Unit,
+    in_group_even: bool,
+    config: &Config,
+) -> Result<(), Response<Body>> {
+    let deadline = if in_group_even {
+        &unit.deadline_group_even
+    } else {
+        &unit.deadline_group_odd
+    };
+    let locked_at = match deadlines::lock_time(
+        deadline,
+        config.deadline_time_zone,
+        config.reservation_lock_cutoff,
+    ) {
+        Some(val) => val,
+        None => return Ok(()),
+    };
+    if Utc::now() < locked_at {
+        return Ok(());
+    }
+    Err(json(
+        &serde_json::json!({
+            "error": "exerciseLocked",
+            "lockedAt": locked_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        }),
+        StatusCode::CONFLICT,
+    ))
+}
+
+/// Checks that a student can reserve one more exercise of a unit, or builds
+/// the response explaining which limit of the unit would be exceeded. The
+/// exercises that were presented do not count.
+#[allow(clippy::result_large_err)]
+fn check_reservation_limits(
+    db: &Connection,
+    unit: &Unit,
+    exercise_index: u32,
+    student_id: u32,
+) -> Result<(), Response<Body>> {
+    if let Some(max) = unit.max_reservers_per_exercise {
+        if !exercise_has_room(db, unit, exercise_index, student_id) {
+            return Err(json(
+                &serde_json::json!({
+                    "error": "exerciseFull",
+                    "limit": max,
+                    "perGroup": unit.max_reservers_per_group,
+                }),
+                StatusCode::CONFLICT,
+            ));
+        }
+    }
+    check_student_reservations(db, unit, exercise_index, student_id)
+}
+
+/// Whether the maximum number of reservers of an exercise leaves room for a
+/// student, who is not counted.
+fn exercise_has_room(db: &Connection, unit: &Unit, exercise_index: u32, student_id: u32) -> bool {
+    let max = match unit.max_reservers_per_exercise {
+        Some(val) => val,
+        None => return true,
+    };
+    let reservers: u32 = db
+        .query_row(
+            "SELECT COUNT(*) FROM exercise_student_state INNER JOIN students ON exercise_student_state.student_id = students.id WHERE unit_id = ?1 AND exercise_index = ?2 AND state = 0 AND student_id != ?3 AND (NOT ?4 OR in_group_even = (SELECT in_group_even FROM students WHERE id = ?3))",
+            params![
+                unit.id,
+                exercise_index,
+                student_id,
+                unit.max_reservers_per_group
+            ],
+            |r| r.get(0),
+        )
+        .unwrap();
+    reservers < max
+}
+
+/// Checks the maximum number of reservations of a student in a unit, or
+/// builds the response refusing one more. Waiting for an exercise counts as a
+/// reservation, so that the student can be given the exercise at any time.
+#[allow(clippy::result_large_err)]
+fn check_student_reservations(
+    db: &Connection,
+    unit: &Unit,
+    exercise_index: u32,
+    student_id: u32,
+) -> Result<(), Response<Body>> {
+    if let Some(max) = unit.max_reservations_per_student {
+        let reservations: u32 = db
+            .query_row(
+                "SELECT COUNT(*) FROM exercise_student_state WHERE unit_id = ? AND student_id = ? AND state IN (0, 2) AND exercise_index != ?",
+                params![unit.id, student_id, exercise_index],
+                |r| r.get(0),
+            )
+            .unwrap();
+        if reservations >= max {
+            return Err(json(
+                &serde_json::json!({
+                    "error": "tooManyReservations",
+                    "limit": max,
+                }),
+                StatusCode::CONFLICT,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Gives the reservations of an exercise that are available to the students
+/// who have been waiting for them the longest. `actor_id` is the student who
+/// released a reservation or raised the limit.
+fn promote_waitlisted(
+    db: &Connection,
+    req: &Request<Body>,
+    config: &Config,
+    actor_id: u32,
+    unit: &Unit,
+    exercise_index: u32,
+) {
+    let waiting: Vec<u32> = db
+        .prepare("SELECT student_id FROM exercise_student_state WHERE unit_id = ? AND exercise_index = ? AND state = 2 ORDER BY waitlist_order")
+        .unwrap()
+        .query_map(params![unit.id, exercise_index], |r| r.get(0))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    // With a limit per group, a student of the other group may get a
+    // reservation even if the first one in the list does not.
+    for student_id in waiting {
+        if !exercise_has_room(db, unit, exercise_index, student_id) {
+            continue;
+        }
+        db.execute(
+            "UPDATE exercise_student_state SET state = 0, waitlist_order = NULL, reserved_at = CURRENT_TIMESTAMP WHERE student_id = ? AND unit_id = ? AND exercise_index = ?",
+            params![student_id, unit.id, exercise_index],
+        )
+        .unwrap();
+        audit::record_for_req(
+            db,
+            req,
+            config,
+            actor_id,
+            audit::Event::new(audit::Action::SetExerciseState)
+                .exercise(unit.id, exercise_index)
+                .old_value(&serde_json::json!({
+                    "studentId": student_id,
+                    "state": state_name(Some(2)),
+                }))
+                .new_value(&serde_json::json!({
+                    "studentId": student_id,
+                    "state": state_name(Some(0)),
+                })),
+        );
+    }
+}
+
+const MAX_PICTURE_SIZE: usize = 1024 * 1024 * 5;
+// Every page of a document has the same limits as a picture.
+const MAX_DOCUMENT_SIZE: usize = MAX_PICTURE_SIZE * 4;
+
 pub(crate) async fn submit_exercise_correction(
     mut req: Request<Body>,
     unit_id: u32,
     exercise_index: u32,
     db: &Mutex<Connection>,
+    pictures_lock: &Mutex<()>,
     config: &Config,
 ) -> Response<Body> {
-    let student_id = match get_logged_in_user_id(&req, config) {
+    let principal = match get_logged_in_principal(&req, db, config).await {
         Ok(val) => val,
         Err(err) => {
             warn_for_req(
@@ -470,6 +1200,7 @@ pub(crate) async fn submit_exercise_correction(
             return empty(StatusCode::FORBIDDEN);
         }
     };
+    let student_id = principal.student_id;
 
     let exercise_count: u32 = {
         let db = db.lock().await;
@@ -502,9 +1233,16 @@ pub(crate) async fn submit_exercise_correction(
         return empty(StatusCode::NOT_FOUND);
     }
 
-    const MAX_PICTURE_SIZE: usize = 1024 * 1024 * 5;
-
-    let b = match collect_body(req.body_mut(), MAX_PICTURE_SIZE).await {
+    let is_pdf = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .is_some_and(|v| v == "application/pdf");
+    let max_size = if is_pdf {
+        MAX_DOCUMENT_SIZE
+    } else {
+        MAX_PICTURE_SIZE
+    };
+    let b = match collect_body(req.body_mut(), max_size).await {
         Ok(val) => val,
         Err(CollectBodyError::ReadError(err)) => {
             warn_for_req(
@@ -519,86 +1257,347 @@ pub(crate) async fn submit_exercise_correction(
             return empty(StatusCode::PAYLOAD_TOO_LARGE);
         }
     };
-    let mut reader = ImageReader::new(Cursor::new(b));
-    if let Some(v) = req.headers().get(http::header::CONTENT_TYPE) {
-        if v == "image/png" {
-            reader.set_format(ImageFormat::Png);
-        } else if v == "image/jpeg" {
-            reader.set_format(ImageFormat::Jpeg);
-        } else if v == "image/gif" {
-            reader.set_format(ImageFormat::Gif);
-        } else if v == "image/webp" {
-            reader.set_format(ImageFormat::WebP);
-        } else if v == "image/tiff" {
-            reader.set_format(ImageFormat::Tiff);
-        } else if v == "image/bmp" {
-            reader.set_format(ImageFormat::Bmp);
-        } else if v == "image/x-icon" {
-            reader.set_format(ImageFormat::Ico);
-        } else if v == "image/avif" {
-            reader.set_format(ImageFormat::Avif);
-        }
-    }
-    if reader.format().is_none() {
-        reader = match reader.with_guessed_format() {
-            Ok(val) => val,
-            Err(err) => {
-                warn_for_req(
-                    &req,
-                    config,
-                    &format!(
-                        "failed to guess image format for correction picture: {:?}",
-                        err
-                    ),
+    // Decoding, cleaning up and encoding the pictures takes a while, so it is
+    // done on a blocking thread to keep serving the other requests meanwhile.
+    let content_type = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+    // Photos of paper are only cleaned up if the student asks for it, since
+    // it is slow and the correction may rely on colours.
+    let clean_up = get_query_param(&req, "clean_up").is_some_and(|v| v == "true");
+    let encoded = tokio::task::spawn_blocking(move || {
+        encode_correction_pictures(b, is_pdf, content_type.as_deref(), clean_up)
+    })
+    .await
+    .expect("failed to encode correction pictures");
+    let pictures = match encoded {
+        Ok(val) => val,
+        Err(err) => {
+            warn_for_req(&req, config, &err.message);
+            return match err.body {
+                Some(body) => json(&body, err.status),
+                None => empty(err.status),
+            };
+        }
+    };
+
+    // Near-identical pictures are usually the same sheet photographed twice,
+    // so they are refused unless the student insists.
+    let allow_similar = get_query_param(&req, "allow_similar").is_some_and(|v| v == "true");
+    if !allow_similar {
+        let db = db.lock().await;
+        for picture in &pictures {
+            let similar = corrections::find_similar(
+                &db,
+                unit_id,
+                exercise_index,
+                &picture.digest_base64,
+                picture.perceptual_hash,
+            );
+            if let Some(digest) = similar {
+                return json(
+                    &SimilarCorrection {
+                        duplicate_of: digest,
+                    },
+                    StatusCode::CONFLICT,
                 );
-                return empty(StatusCode::BAD_REQUEST);
             }
+        }
+    }
+
+    // The pages of a document that were already submitted are skipped.
+    let mut stored = Vec::new();
+    let _pictures_lock = pictures_lock.lock().await;
+    for picture in pictures {
+        let digest = picture.digest_base64.clone();
+        match store_correction_picture(
+            &req,
+            unit_id,
+            exercise_index,
+            &principal,
+            picture,
+            db,
+            config,
+        )
+        .await
+        {
+            Ok(StoredCorrection::Existing) => {}
+            Ok(outcome) => stored.push((digest, outcome)),
+            Err(res) => return res,
+        }
+    }
+    if stored.is_empty() {
+        // The correction already exists.
+        return empty(StatusCode::CONFLICT);
+    }
+
+    let db = db.lock().await;
+    for (digest, outcome) in stored {
+        let event = match outcome {
+            StoredCorrection::Restored => audit::Event::new(audit::Action::RestoreCorrection),
+            StoredCorrection::Resubmitted {
+                created_by,
+                deleted_at,
+            } => audit::Event::new(audit::Action::SubmitCorrection).old_value(&serde_json::json!({
+                "digest": digest,
+                "createdBy": created_by,
+                "deletedAt": deleted_at,
+            })),
+            _ => audit::Event::new(audit::Action::SubmitCorrection),
         };
+        audit::record_for_req(
+            &db,
+            &req,
+            config,
+            student_id,
+            event.exercise(unit_id, exercise_index).new_value(&digest),
+        );
     }
 
-    let image = match reader.decode() {
+    empty(StatusCode::OK)
+}
+
+#[derive(Serialize)]
+struct SimilarCorrection {
+    /// The digest of the existing correction that looks like the submitted
+    /// picture.
+    #[serde(rename = "duplicateOf")]
+    duplicate_of: String,
+}
+
+/// A correction picture that is ready to be stored.
+struct EncodedPicture {
+    digest_base64: String,
+    perceptual_hash: i64,
+    png: Vec<u8>,
+    variants: Vec<(u32, Vec<u8>)>,
+}
+
+/// Why a submitted correction could not be turned into pictures.
+struct EncodeError {
+    status: StatusCode,
+    message: String,
+    /// The body of the response, if the front end can tell the user more
+    /// than the status does.
+    body: Option<serde_json::Value>,
+}
+
+fn encode_error<T>(status: StatusCode, message: impl Into<String>) -> Result<T, EncodeError> {
+    Err(EncodeError {
+        status,
+        message: message.into(),
+        body: None,
+    })
+}
+
+/// Decodes a submitted picture or the pages of a scanned document, maybe
+/// cleans them up, and encodes them for storage.
+///
+/// Everything is encoded before anything is stored, so that an invalid page
+/// does not leave the first pages of a document behind. The pages are decoded
+/// one at a time, and only the encoded ones are kept.
+fn encode_correction_pictures(
+    data: Vec<u8>,
+    is_pdf: bool,
+    content_type: Option<&str>,
+    clean_up: bool,
+) -> Result<Vec<EncodedPicture>, EncodeError> {
+    if is_pdf || scanned_pdf::is_pdf(&data) {
+        let pdf_error = |err: scanned_pdf::PdfError| {
+            let code = match err.kind {
+                PdfErrorKind::Invalid => "pdfInvalid",
+                PdfErrorKind::NotScanned => "pdfNotScanned",
+                PdfErrorKind::Unsupported => "pdfUnsupported",
+                PdfErrorKind::TooLarge => "pdfTooLarge",
+            };
+            Err(EncodeError {
+                status: StatusCode::BAD_REQUEST,
+                message: format!("failed to extract pages from correction PDF: {}", err),
+                body: Some(serde_json::json!({ "error": code, "page": err.page })),
+            })
+        };
+        let pages = match scanned_pdf::scanned_pages(&data) {
+            Ok(val) => val,
+            Err(err) => return pdf_error(err),
+        };
+        let mut pictures = Vec::new();
+        for page in pages {
+            match page {
+                Ok(image) => pictures.push(encode_correction_picture(image, clean_up)?),
+                Err(err) => return pdf_error(err),
+            }
+        }
+        return Ok(pictures);
+    }
+
+    // The dimensions are read from the headers first, so that a small file
+    // cannot make the decoder allocate a huge picture.
+    match correction_picture_reader(&data, content_type)?.into_dimensions() {
+        Ok((width, height)) if width <= 10_000 && height <= 10_000 => {}
+        Ok(_) => {
+            return encode_error(
+                StatusCode::BAD_REQUEST,
+                "correction picture dimensions exceed limits",
+            )
+        }
+        Err(err) => {
+            return encode_error(
+                StatusCode::BAD_REQUEST,
+                format!("failed to decode correction picture: {:?}", err),
+            )
+        }
+    }
+    let image = match correction_picture_reader(&data, content_type)?.decode() {
         Ok(val) => val,
         Err(err) => {
-            warn_for_req(
-                &req,
-                config,
-                &format!("failed to decode correction picture: {:?}", err),
-            );
-            return empty(StatusCode::BAD_REQUEST);
+            return encode_error(
+                StatusCode::BAD_REQUEST,
+                format!("failed to decode correction picture: {:?}", err),
+            )
         }
     };
+    // Only JPEG files from phones and cameras are expected to be rotated.
+    let image = match metadata::jpeg_orientation(&data) {
+        Some(o) => metadata::apply_orientation(image, o),
+        None => image,
+    };
+    Ok(vec![encode_correction_picture(image, clean_up)?])
+}
+
+/// Prepares the decoding of a submitted picture, in the format given by its
+/// content type or else guessed from its content.
+fn correction_picture_reader<'a>(
+    data: &'a [u8],
+    content_type: Option<&str>,
+) -> Result<ImageReader<Cursor<&'a [u8]>>, EncodeError> {
+    let mut reader = ImageReader::new(Cursor::new(data));
+    match content_type {
+        Some("image/png") => reader.set_format(ImageFormat::Png),
+        Some("image/jpeg") => reader.set_format(ImageFormat::Jpeg),
+        Some("image/gif") => reader.set_format(ImageFormat::Gif),
+        Some("image/webp") => reader.set_format(ImageFormat::WebP),
+        Some("image/tiff") => reader.set_format(ImageFormat::Tiff),
+        Some("image/bmp") => reader.set_format(ImageFormat::Bmp),
+        Some("image/x-icon") => reader.set_format(ImageFormat::Ico),
+        Some("image/avif") => reader.set_format(ImageFormat::Avif),
+        _ => {}
+    }
+    if reader.format().is_some() {
+        return Ok(reader);
+    }
+    match reader.with_guessed_format() {
+        Ok(val) => Ok(val),
+        Err(err) => encode_error(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "failed to guess image format for correction picture: {:?}",
+                err
+            ),
+        ),
+    }
+}
+
+/// Maybe cleans up a decoded correction picture, and encodes it for storage.
+fn encode_correction_picture(
+    image: DynamicImage,
+    clean_up: bool,
+) -> Result<EncodedPicture, EncodeError> {
+    let image = if clean_up {
+        cleanup::clean_up(&image)
+    } else {
+        image
+    };
     if image.width() > 10_000 || image.height() > 10_000 {
-        warn_for_req(&req, config, "correction picture dimensions exceed limits");
-        return empty(StatusCode::BAD_REQUEST);
+        return encode_error(
+            StatusCode::BAD_REQUEST,
+            "correction picture dimensions exceed limits",
+        );
     }
 
     let mut png = Vec::new();
     if let Err(err) = image.write_to(&mut png, ImageOutputFormat::Png) {
-        warn_for_req(
-            &req,
-            config,
-            &format!("failed to encode correction picture as PNG: {:?}", err),
+        return encode_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to encode correction picture as PNG: {:?}", err),
         );
-        return empty(StatusCode::INTERNAL_SERVER_ERROR);
     }
+    let png = metadata::strip_png(&png);
     if png.len() > MAX_PICTURE_SIZE {
-        warn_for_req(&req, config, "encoded PNG image is too large");
-        return empty(StatusCode::PAYLOAD_TOO_LARGE);
+        return encode_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "encoded PNG image is too large",
+        );
     }
+    let variants = match corrections::encode_variants(&image) {
+        Ok(val) => val,
+        Err(err) => {
+            return encode_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to encode correction picture variants: {:?}", err),
+            )
+        }
+    };
 
     let mut hash = Sha256::new();
     hash.update(&png);
-    let digest = hash.finalize();
-    let digest_base64 = base64::encode_config(digest.as_slice(), base64::URL_SAFE_NO_PAD);
+    let digest_base64 = base64::encode_config(hash.finalize().as_slice(), base64::URL_SAFE_NO_PAD);
+    Ok(EncodedPicture {
+        digest_base64,
+        perceptual_hash: corrections::perceptual_hash(&image),
+        png,
+        variants,
+    })
+}
 
-    {
+/// What storing a correction picture did.
+enum StoredCorrection {
+    /// The exercise already has this correction.
+    Existing,
+    Created,
+    /// The correction was in the trash, from which the student may restore
+    /// it.
+    Restored,
+    /// The correction was in the trash, and the student may not restore it,
+    /// so it is taken out of the trash as a new submission. It is still
+    /// credited to its first uploader, and the student who submitted it again
+    /// is only recorded in the audit log.
+    Resubmitted {
+        created_by: Option<u32>,
+        /// When the correction was moved to the trash, in RFC 3339 format.
+        deleted_at: String,
+    },
+}
+
+/// Stores a correction picture along with its variants.
+///
+/// The caller must hold the pictures lock, so that the garbage collection does
+/// not run between the creation of the entry and the writing of the picture.
+async fn store_correction_picture(
+    req: &Request<Body>,
+    unit_id: u32,
+    exercise_index: u32,
+    principal: &Principal,
+    picture: EncodedPicture,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Result<StoredCorrection, Response<Body>> {
+    let digest_base64 = picture.digest_base64.as_str();
+    let outcome = {
         let db = db.lock().await;
         let mut stmt = db.prepare(
-            "INSERT INTO exercise_corrections (unit_id, unit_exercise, created_by, picture_digest) VALUES (?, ?, ?, ?)"
+            "INSERT INTO exercise_corrections (unit_id, unit_exercise, created_by, picture_digest, perceptual_hash) VALUES (?, ?, ?, ?, ?)"
         ).unwrap();
-        if let Err(err) = stmt.execute(params![unit_id, exercise_index, student_id, digest_base64])
-        {
-            match err {
+        match stmt.execute(params![
+            unit_id,
+            exercise_index,
+            principal.student_id,
+            digest_base64,
+            picture.perceptual_hash
+        ]) {
+            Ok(_) => StoredCorrection::Created,
+            Err(err) => match err {
                 SqliteError::SqliteFailure(
                     rusqlite::ffi::Error {
                         code: SqliteErrorCode::ConstraintViolation,
@@ -607,28 +1606,61 @@ pub(crate) async fn submit_exercise_correction(
                     _,
                 ) => {
                     // The unique constraint is violated because the
-                    // correction already exists.
-                    return empty(StatusCode::CONFLICT);
+                    // correction already exists, but it may be in the trash.
+                    let age = corrections::sqlite_age(config.corrections_trash_retention);
+                    let trashed: Option<(Option<u32>, Option<u32>, bool, String)> = db
+                        .query_row(
+                            "SELECT created_by, deleted_by, deleted_at >= datetime('now', ?), strftime('%Y-%m-%dT%H:%M:%SZ', deleted_at) FROM exercise_corrections WHERE unit_id = ? AND unit_exercise = ? AND picture_digest = ? AND deleted_at IS NOT NULL",
+                            params![age, unit_id, exercise_index, digest_base64],
+                            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+                        )
+                        .optional()
+                        .unwrap();
+                    let outcome = match trashed {
+                        None => return Ok(StoredCorrection::Existing),
+                        // Uploading it again restores it, with the same rules
+                        // as restoring it from the trash.
+                        Some((created_by, deleted_by, true, _))
+                            if principal.is_teacher()
+                                || created_by == Some(principal.student_id)
+                                || deleted_by == Some(principal.student_id) =>
+                        {
+                            StoredCorrection::Restored
+                        }
+                        Some((created_by, _, _, deleted_at)) => StoredCorrection::Resubmitted {
+                            created_by,
+                            deleted_at,
+                        },
+                    };
+                    db.execute(
+                        "UPDATE exercise_corrections SET deleted_at = NULL, deleted_by = NULL WHERE unit_id = ? AND unit_exercise = ? AND picture_digest = ?",
+                        params![unit_id, exercise_index, digest_base64],
+                    )
+                    .unwrap();
+                    outcome
                 }
                 _ => {
                     warn_for_req(
-                        &req,
+                        req,
                         config,
                         &format!("failed to insert correction entry: {:?}", err),
                     );
-                    return empty(StatusCode::INTERNAL_SERVER_ERROR);
+                    return Err(empty(StatusCode::INTERNAL_SERVER_ERROR));
                 }
-            }
+            },
         }
-    }
+    };
+    // A correction that was in the trash keeps its entry, since its picture
+    // was kept along with it.
+    let created = matches!(outcome, StoredCorrection::Created);
 
     let p = config
         .corrections_path
-        .join(format!("{}.png", digest_base64));
+        .join(corrections::picture_file_name(digest_base64));
     let mut png_file = match OpenOptions::new()
         .write(true)
         .create_new(true)
-        .open(p)
+        .open(&p)
         .await
     {
         Ok(val) => val,
@@ -636,27 +1668,72 @@ pub(crate) async fn submit_exercise_correction(
             if err.kind() == ErrorKind::AlreadyExists {
                 // Assume that the files are the same since they have the same
                 // hash so we can stop here and use the old file.
-                return empty(StatusCode::OK);
+                return Ok(outcome);
             }
             warn_for_req(
-                &req,
+                req,
                 config,
                 &format!("failed to open correction picture for writing: {:?}", err),
             );
-            return empty(StatusCode::INTERNAL_SERVER_ERROR);
+            if created {
+                forget_correction(db, unit_id, exercise_index, digest_base64).await;
+            }
+            return Err(empty(StatusCode::INTERNAL_SERVER_ERROR));
         }
     };
 
-    if let Err(err) = png_file.write_all(&png).await {
+    if let Err(err) = png_file.write_all(&picture.png).await {
         warn_for_req(
-            &req,
+            req,
             config,
             &format!("failed to write correction picture: {:?}", err),
         );
-        return empty(StatusCode::INTERNAL_SERVER_ERROR);
+        // Do not leave a truncated picture behind.
+        if let Err(err) = tokio::fs::remove_file(&p).await {
+            warn_for_req(
+                req,
+                config,
+                &format!("failed to remove truncated correction picture: {:?}", err),
+            );
+        }
+        if created {
+            forget_correction(db, unit_id, exercise_index, digest_base64).await;
+        }
+        return Err(empty(StatusCode::INTERNAL_SERVER_ERROR));
     }
 
-    empty(StatusCode::OK)
+    // The original is served when a variant is missing, so failing to write
+    // one is not fatal.
+    for (width, jpeg) in picture.variants {
+        let p = config
+            .corrections_path
+            .join(corrections::variant_file_name(digest_base64, width));
+        if let Err(err) = tokio::fs::write(&p, jpeg).await {
+            warn_for_req(
+                req,
+                config,
+                &format!("failed to write correction picture variant: {:?}", err),
+            );
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Removes the entry of a correction whose picture could not be written, so
+/// that it does not point to a file that does not exist.
+async fn forget_correction(
+    db: &Mutex<Connection>,
+    unit_id: u32,
+    exercise_index: u32,
+    digest: &str,
+) {
+    let db = db.lock().await;
+    db.execute(
+        "DELETE FROM exercise_corrections WHERE unit_id = ? AND unit_exercise = ? AND picture_digest = ?",
+        params![unit_id, exercise_index, digest],
+    )
+    .unwrap();
 }
 
 pub(crate) async fn delete_exercise_correction(
@@ -667,21 +1744,44 @@ pub(crate) async fn delete_exercise_correction(
     db: &Mutex<Connection>,
     config: &Config,
 ) -> Response<Body> {
-    // Make sure that the user is logged in.
-    if let Err(err) = get_logged_in_user_id(&req, config) {
-        warn_for_req(
-            &req,
-            config,
-            &format!(
-                "exercise correction deletion request with invalid authentication: {:?}",
-                err
-            ),
-        );
-        return empty(StatusCode::FORBIDDEN);
+    let principal = match get_logged_in_principal(&req, db, config).await {
+        Ok(val) => val,
+        Err(err) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!(
+                    "exercise correction deletion request with invalid authentication: {:?}",
+                    err
+                ),
+            );
+            return empty(StatusCode::FORBIDDEN);
+        }
     };
 
     let db = db.lock().await;
-    let mut stmt = match db.prepare("DELETE FROM exercise_corrections WHERE unit_id = ? AND unit_exercise = ? AND picture_digest = ?") {
+    // Deleting the pictures of others is a moderation action.
+    if !principal.is_teacher() {
+        let created_by: Option<Option<u32>> = db
+            .query_row(
+                "SELECT created_by FROM exercise_corrections WHERE unit_id = ? AND unit_exercise = ? AND picture_digest = ? AND deleted_at IS NULL",
+                params![unit_id, exercise_index, correction_digest],
+                |r| r.get(0),
+            )
+            .optional()
+            .unwrap();
+        if created_by.is_some_and(|id| id != Some(principal.student_id)) {
+            warn_for_req(
+                &req,
+                config,
+                "exercise correction deletion request from a student who did not upload it",
+            );
+            return empty(StatusCode::FORBIDDEN);
+        }
+    }
+    // The correction is only moved to the trash, from which it can be
+    // restored until it is purged.
+    let mut stmt = match db.prepare("UPDATE exercise_corrections SET deleted_at = CURRENT_TIMESTAMP, deleted_by = ? WHERE unit_id = ? AND unit_exercise = ? AND picture_digest = ? AND deleted_at IS NULL") {
         Ok(val) => val,
         Err(err) => {
             warn_for_req(
@@ -698,13 +1798,30 @@ pub(crate) async fn delete_exercise_correction(
 
     // If the correction entry was not found, then the statement won't return
     // an error so we will return OK too.
-    if let Err(err) = stmt.execute(params![unit_id, exercise_index, correction_digest]) {
-        warn_for_req(
+    match stmt.execute(params![
+        principal.student_id,
+        unit_id,
+        exercise_index,
+        correction_digest
+    ]) {
+        Ok(0) => {}
+        Ok(_) => audit::record_for_req(
+            &db,
             &req,
             config,
-            &format!("failed to delete exercise correction: {:?}", err),
-        );
-        return empty(StatusCode::INTERNAL_SERVER_ERROR);
+            principal.student_id,
+            audit::Event::new(audit::Action::DeleteCorrection)
+                .exercise(unit_id, exercise_index)
+                .old_value(&correction_digest),
+        ),
+        Err(err) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!("failed to delete exercise correction: {:?}", err),
+            );
+            return empty(StatusCode::INTERNAL_SERVER_ERROR);
+        }
     }
 
     empty(StatusCode::OK)
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, HashSet};
+
+use http::StatusCode;
+use hyper::{Body, Request, Response};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{get_unit, state_name};
+use crate::assignment::{self, Candidate, Exercises};
+use crate::audit;
+use crate::config::Config;
+use crate::http_helpers::*;
+
+#[derive(Deserialize)]
+struct Preferences {
+    #[serde(rename = "studentId")]
+    student_id: u32,
+    /// The indices of the exercises, the preferred one first.
+    exercises: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+struct AssignExercisesRequest {
+    /// The same seed and preferences always give the same assignment.
+    #[serde(default)]
+    seed: u64,
+    /// Computes the assignment without reserving anything.
+    #[serde(default)]
+    preview: bool,
+    /// How many exercises each student should end up with, counting the
+    /// ones that they already reserved.
+    #[serde(
+        rename = "exercisesPerStudent",
+        default = "default_exercises_per_student"
+    )]
+    exercises_per_student: u32,
+    preferences: Vec<Preferences>,
+}
+
+fn default_exercises_per_student() -> u32 {
+    1
+}
+
+#[derive(Serialize)]
+struct AssignedExercise {
+    #[serde(rename = "studentId")]
+    student_id: u32,
+    #[serde(rename = "exerciseIndex")]
+    exercise_index: u32,
+    /// The position of the exercise in the preferences of the student,
+    /// starting at 1.
+    rank: u32,
+}
+
+#[derive(Serialize)]
+struct AssignExercisesResponse {
+    seed: u64,
+    preview: bool,
+    assignments: Vec<AssignedExercise>,
+    /// The students who got fewer exercises than they should have, because
+    /// the ones that they wanted were full or blocked.
+    unassigned: Vec<u32>,
+}
+
+/// Reserves exercises of a unit for students from their ranked preferences,
+/// respecting the limits of the unit and taking turns between the groups.
+/// The current reservations are kept and count towards the limits.
+pub(crate) async fn assign_exercises(
+    mut req: Request<Body>,
+    unit_id: u32,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "exercise assignment request";
+    let principal = match authenticate_teacher(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    let r: AssignExercisesRequest = match read_json_body(&mut req, 256 * 1024, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let db = db.lock().await;
+    let unit = match get_unit(&db, unit_id) {
+        Some(val) => val,
+        None => return empty(StatusCode::NOT_FOUND),
+    };
+    let mut seen = HashSet::new();
+    for p in &r.preferences {
+        let mut exercises = HashSet::new();
+        if !seen.insert(p.student_id)
+            || !p
+                .exercises
+                .iter()
+                .all(|&e| e < unit.exercise_count && exercises.insert(e))
+        {
+            warn_for_req(&req, config, &format!("{} with invalid preferences", WHAT));
+            return empty(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let tx = db.unchecked_transaction().unwrap();
+    let mut exercises = Exercises {
+        max_reservers: unit.max_reservers_per_exercise,
+        per_group: unit.max_reservers_per_group,
+        reservers: vec![(0, 0); unit.exercise_count as usize],
+        blocked: vec![false; unit.exercise_count as usize],
+    };
+    {
+        let mut stmt = tx
+            .prepare("SELECT index_ FROM exercise WHERE unit_id = ? AND blocked")
+            .unwrap();
+        let mut rows = stmt.query(params![unit_id]).unwrap();
+        while let Some(r) = rows.next().unwrap() {
+            let index: u32 = r.get(0).unwrap();
+            exercises.blocked[index as usize] = true;
+        }
+        let mut stmt = tx
+            .prepare("SELECT exercise_index, in_group_even, COUNT(*) FROM exercise_student_state INNER JOIN students ON exercise_student_state.student_id = students.id WHERE unit_id = ? AND state = 0 GROUP BY exercise_index, in_group_even")
+            .unwrap();
+        let mut rows = stmt.query(params![unit_id]).unwrap();
+        while let Some(r) = rows.next().unwrap() {
+            let index: u32 = r.get(0).unwrap();
+            let in_group_even: bool = r.get(1).unwrap();
+            let count: u32 = r.get(2).unwrap();
+            let (even, odd) = &mut exercises.reservers[index as usize];
+            if in_group_even {
+                *even = count;
+            } else {
+                *odd = count;
+            }
+        }
+    }
+
+    let wanted = match unit.max_reservations_per_student {
+        Some(max) => r.exercises_per_student.min(max),
+        None => r.exercises_per_student,
+    };
+    let mut candidates = Vec::with_capacity(r.preferences.len());
+    for p in r.preferences {
+        let in_group_even: Option<bool> = tx
+            .query_row(
+                "SELECT in_group_even FROM students WHERE id = ? AND active",
+                params![p.student_id],
+                |r| r.get(0),
+            )
+            .optional()
+            .unwrap();
+        let in_group_even = match in_group_even {
+            Some(val) => val,
+            None => {
+                return json(
+                    &serde_json::json!({
+                        "error": "unknownStudent",
+                        "studentId": p.student_id,
+                    }),
+                    StatusCode::CONFLICT,
+                )
+            }
+        };
+        let mut taken = HashSet::new();
+        let mut reservations = 0;
+        let mut stmt = tx
+            .prepare("SELECT exercise_index, state FROM exercise_student_state WHERE unit_id = ? AND student_id = ?")
+            .unwrap();
+        let mut rows = stmt.query(params![unit_id, p.student_id]).unwrap();
+        while let Some(r) = rows.next().unwrap() {
+            let index: u32 = r.get(0).unwrap();
+            let state: u32 = r.get(1).unwrap();
+            taken.insert(index);
+            if state != 1 {
+                reservations += 1;
+            }
+        }
+        candidates.push(Candidate {
+            id: p.student_id,
+            in_group_even,
+            preferences: p.exercises,
+            wanted: wanted.saturating_sub(reservations),
+            taken,
+        });
+    }
+
+    let result = assignment::assign(&candidates, &mut exercises, r.seed);
+    let mut assigned: HashMap<u32, u32> = HashMap::new();
+    for a in &result {
+        *assigned.entry(a.student_id).or_default() += 1;
+    }
+    let mut unassigned: Vec<u32> = candidates
+        .iter()
+        .filter(|c| assigned.get(&c.id).copied().unwrap_or(0) < c.wanted)
+        .map(|c| c.id)
+        .collect();
+    unassigned.sort_unstable();
+
+    if !r.preview {
+        for a in &result {
+            tx.execute(
+                "INSERT INTO exercise_student_state (student_id, unit_id, exercise_index, state, reserved_at) VALUES (?, ?, ?, 0, CURRENT_TIMESTAMP)",
+                params![a.student_id, unit_id, a.exercise_index],
+            )
+            .unwrap();
+            audit::record_for_req(
+                &tx,
+                &req,
+                config,
+                principal.student_id,
+                audit::Event::new(audit::Action::SetExerciseState)
+                    .exercise(unit_id, a.exercise_index)
+                    .old_value(&serde_json::json!({
+                        "studentId": a.student_id,
+                        "state": state_name(None),
+                    }))
+                    .new_value(&serde_json::json!({
+                        "studentId": a.student_id,
+                        "state": state_name(Some(0)),
+                    })),
+            );
+        }
+        tx.commit().unwrap();
+    }
+
+    json(
+        &AssignExercisesResponse {
+            seed: r.seed,
+            preview: r.preview,
+            assignments: result
+                .into_iter()
+                .map(|a| AssignedExercise {
+                    student_id: a.student_id,
+                    exercise_index: a.exercise_index,
+                    rank: a.rank,
+                })
+                .collect(),
+            unassigned,
+        },
+        StatusCode::OK,
+    )
+}
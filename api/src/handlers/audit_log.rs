@@ -0,0 +1,138 @@
+use http::StatusCode;
+use hyper::{Body, Request, Response};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use crate::config::Config;
+use crate::http_helpers::*;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+
+#[derive(Deserialize)]
+struct AuditLogQuery {
+    #[serde(rename = "actorId")]
+    actor_id: Option<u32>,
+    action: Option<String>,
+    #[serde(rename = "unitId")]
+    unit_id: Option<u32>,
+    #[serde(rename = "exerciseIndex")]
+    exercise_index: Option<u32>,
+    /// Only returns the entries that are older than this one, to fetch the
+    /// next page.
+    before: Option<i64>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct AuditEntry {
+    id: i64,
+    /// In RFC 3339 format.
+    #[serde(rename = "createdAt")]
+    created_at: String,
+    /// Missing for the commands run on the command line.
+    #[serde(rename = "actorId")]
+    actor_id: Option<u32>,
+    /// Missing if the account of the actor was deleted since.
+    #[serde(rename = "actorUsername")]
+    actor_username: Option<String>,
+    action: String,
+    #[serde(rename = "unitId")]
+    unit_id: Option<u32>,
+    #[serde(rename = "exerciseIndex")]
+    exercise_index: Option<u32>,
+    #[serde(rename = "oldValue")]
+    old_value: Option<Value>,
+    #[serde(rename = "newValue")]
+    new_value: Option<Value>,
+    ip: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AuditLogPage {
+    /// The newest entries first.
+    entries: Vec<AuditEntry>,
+    /// The value of `before` that fetches the next page, missing on the last
+    /// page.
+    #[serde(rename = "nextBefore")]
+    next_before: Option<i64>,
+}
+
+/// Reads the audit log, newest entries first, a page at a time.
+pub(crate) async fn audit_log(
+    req: Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "audit log request";
+    if let Err(res) = authenticate_teacher(&req, db, config, WHAT).await {
+        return res;
+    }
+    let q: AuditLogQuery = match parse_query(&req, config, WHAT) {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    let limit = q.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        warn_for_req(&req, config, "audit log request with an invalid limit");
+        return empty(StatusCode::BAD_REQUEST);
+    }
+
+    let db = db.lock().await;
+    let mut stmt = db
+        .prepare(
+            "SELECT audit_log.id, strftime('%Y-%m-%dT%H:%M:%SZ', created_at), actor_id, username, action, unit_id, exercise_index, old_value, new_value, ip \
+             FROM audit_log LEFT JOIN students ON audit_log.actor_id = students.id \
+             WHERE (?1 IS NULL OR actor_id = ?1) AND (?2 IS NULL OR action = ?2) AND (?3 IS NULL OR unit_id = ?3) AND (?4 IS NULL OR exercise_index = ?4) AND (?5 IS NULL OR audit_log.id < ?5) \
+             ORDER BY audit_log.id DESC LIMIT ?6",
+        )
+        .unwrap();
+    // One more entry is fetched to tell whether there is a next page.
+    let mut entries: Vec<AuditEntry> = stmt
+        .query_map(
+            params![
+                q.actor_id,
+                q.action,
+                q.unit_id,
+                q.exercise_index,
+                q.before,
+                limit + 1
+            ],
+            |r| {
+                let old_value: Option<String> = r.get(7)?;
+                let new_value: Option<String> = r.get(8)?;
+                Ok(AuditEntry {
+                    id: r.get(0)?,
+                    created_at: r.get(1)?,
+                    actor_id: r.get(2)?,
+                    actor_username: r.get(3)?,
+                    action: r.get(4)?,
+                    unit_id: r.get(5)?,
+                    exercise_index: r.get(6)?,
+                    old_value: old_value.map(|v| serde_json::from_str(&v).unwrap()),
+                    new_value: new_value.map(|v| serde_json::from_str(&v).unwrap()),
+                    ip: r.get(9)?,
+                })
+            },
+        )
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+
+    let next_before = if entries.len() > limit as usize {
+        entries.truncate(limit as usize);
+        entries.last().map(|e| e.id)
+    } else {
+        None
+    };
+
+    json(
+        &AuditLogPage {
+            entries,
+            next_before,
+        },
+        StatusCode::OK,
+    )
+}
@@ -0,0 +1,221 @@
+use http::StatusCode;
+use hyper::{Body, Request, Response};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::get_unit;
+use crate::audit;
+use crate::config::Config;
+use crate::http_helpers::*;
+
+const MAX_FEEDBACK_LEN: usize = 5000;
+
+#[derive(Deserialize)]
+struct PresentationFeedbackRequest {
+    /// `null` removes the grade.
+    grade: Option<f64>,
+    /// `null` or an empty string removes the comment.
+    feedback: Option<String>,
+}
+
+/// The grade and the comment of a teacher on a presentation.
+#[derive(Serialize)]
+pub(super) struct PresentationFeedback {
+    pub grade: Option<f64>,
+    /// The highest grade of the scale when the grade was given.
+    #[serde(rename = "maxGrade")]
+    pub max_grade: Option<u32>,
+    pub feedback: Option<String>,
+    /// When the feedback was last changed, in RFC 3339 format.
+    #[serde(rename = "gradedAt")]
+    pub graded_at: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StudentFeedback {
+    #[serde(rename = "unitId")]
+    unit_id: u32,
+    #[serde(rename = "exerciseIndex")]
+    exercise_index: u32,
+    #[serde(rename = "presentedAt")]
+    presented_at: Option<String>,
+    #[serde(flatten)]
+    feedback: PresentationFeedback,
+}
+
+/// The columns read by `PresentationFeedback::from_row`, from the given
+/// index.
+pub(super) const FEEDBACK_COLUMNS: &str =
+    "grade, max_grade, feedback, strftime('%Y-%m-%dT%H:%M:%SZ', graded_at)";
+
+impl PresentationFeedback {
+    pub fn from_row(r: &rusqlite::Row, first: usize) -> rusqlite::Result<Self> {
+        Ok(PresentationFeedback {
+            grade: r.get(first)?,
+            max_grade: r.get(first + 1)?,
+            feedback: r.get(first + 2)?,
+            graded_at: r.get(first + 3)?,
+        })
+    }
+
+    /// Whether the teacher left nothing, which is the case until they do.
+    pub fn is_empty(&self) -> bool {
+        self.grade.is_none() && self.feedback.is_none()
+    }
+}
+
+fn get_feedback(
+    db: &Connection,
+    student_id: u32,
+    unit_id: u32,
+    exercise_index: u32,
+) -> PresentationFeedback {
+    db.query_row(
+        &format!(
+            "SELECT {} FROM exercise_student_state WHERE student_id = ? AND unit_id = ? AND exercise_index = ?",
+            FEEDBACK_COLUMNS
+        ),
+        params![student_id, unit_id, exercise_index],
+        |r| PresentationFeedback::from_row(r, 0),
+    )
+    .unwrap()
+}
+
+/// Sets the grade and the comment of a teacher on an exercise presented by a
+/// student.
+pub(crate) async fn set_presentation_feedback(
+    mut req: Request<Body>,
+    unit_id: u32,
+    exercise_index: u32,
+    student_id: u32,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "presentation feedback request";
+    let principal = match authenticate_teacher(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    let r: PresentationFeedbackRequest =
+        match read_json_body(&mut req, 16 * 1024, config, WHAT).await {
+            Ok(val) => val,
+            Err(res) => return res,
+        };
+    let feedback = r
+        .feedback
+        .map(|f| f.trim().to_owned())
+        .filter(|f| !f.is_empty());
+    let is_valid_grade = |g: f64| g.is_finite() && g >= 0.0 && g <= config.max_grade as f64;
+    if !r.grade.is_none_or(is_valid_grade)
+        || feedback.as_ref().map_or(0, |f| f.chars().count()) > MAX_FEEDBACK_LEN
+    {
+        warn_for_req(&req, config, &format!("{} with invalid fields", WHAT));
+        return empty(StatusCode::BAD_REQUEST);
+    }
+
+    let db = db.lock().await;
+    let unit = match get_unit(&db, unit_id) {
+        Some(val) => val,
+        None => return empty(StatusCode::NOT_FOUND),
+    };
+    if exercise_index >= unit.exercise_count {
+        return empty(StatusCode::NOT_FOUND);
+    }
+    let state: Option<u32> = db
+        .query_row(
+            "SELECT state FROM exercise_student_state WHERE student_id = ? AND unit_id = ? AND exercise_index = ?",
+            params![student_id, unit_id, exercise_index],
+            |r| r.get(0),
+        )
+        .optional()
+        .unwrap();
+    if state != Some(1) {
+        return json(
+            &serde_json::json!({ "error": "notPresented" }),
+            StatusCode::CONFLICT,
+        );
+    }
+
+    let tx = db.unchecked_transaction().unwrap();
+    let old = get_feedback(&tx, student_id, unit_id, exercise_index);
+    tx.execute(
+        "UPDATE exercise_student_state SET grade = ?, max_grade = ?, feedback = ?, graded_at = CURRENT_TIMESTAMP WHERE student_id = ? AND unit_id = ? AND exercise_index = ?",
+        params![
+            r.grade,
+            r.grade.map(|_| config.max_grade),
+            feedback,
+            student_id,
+            unit_id,
+            exercise_index
+        ],
+    )
+    .unwrap();
+    let new = get_feedback(&tx, student_id, unit_id, exercise_index);
+    audit::record_for_req(
+        &tx,
+        &req,
+        config,
+        principal.student_id,
+        audit::Event::new(audit::Action::GradePresentation)
+            .exercise(unit_id, exercise_index)
+            .old_value(&serde_json::json!({
+                "studentId": student_id,
+                "grade": old.grade,
+                "maxGrade": old.max_grade,
+                "feedback": old.feedback,
+            }))
+            .new_value(&serde_json::json!({
+                "studentId": student_id,
+                "grade": new.grade,
+                "maxGrade": new.max_grade,
+                "feedback": new.feedback,
+            })),
+    );
+    tx.commit().unwrap();
+
+    json(&new, StatusCode::OK)
+}
+
+/// Lists the grades and comments that a student received on their
+/// presentations. Students can only see their own.
+pub(crate) async fn student_feedback(
+    req: Request<Body>,
+    student_id: u32,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "student feedback request";
+    let principal = match authenticate(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    if !principal.is_teacher() && principal.student_id != student_id {
+        warn_for_req(&req, config, &format!("{} from another student", WHAT));
+        return empty(StatusCode::FORBIDDEN);
+    }
+
+    let db = db.lock().await;
+    let mut stmt = db
+        .prepare(&format!(
+            "SELECT unit_id, exercise_index, strftime('%Y-%m-%dT%H:%M:%SZ', presented_at), {} FROM exercise_student_state \
+             WHERE student_id = ? AND state = 1 AND (grade IS NOT NULL OR feedback IS NOT NULL) \
+             ORDER BY unit_id, exercise_index",
+            FEEDBACK_COLUMNS
+        ))
+        .unwrap();
+    let result: Vec<StudentFeedback> = stmt
+        .query_map(params![student_id], |r| {
+            Ok(StudentFeedback {
+                unit_id: r.get(0)?,
+                exercise_index: r.get(1)?,
+                presented_at: r.get(2)?,
+                feedback: PresentationFeedback::from_row(r, 3)?,
+            })
+        })
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+
+    json(&result, StatusCode::OK)
+}
@@ -0,0 +1,82 @@
+use http::StatusCode;
+use hyper::{Body, Request, Response};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::config::Config;
+use crate::http_helpers::*;
+
+/// Tells a student that someone else changed the state of one of their
+/// exercises.
+#[derive(Serialize)]
+pub(super) struct Notice {
+    /// `reservationCancelled` or `waitlistCancelled` when the exercise was
+    /// blocked.
+    pub kind: String,
+    /// In RFC 3339 format.
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+}
+
+pub(super) fn add_notice(
+    db: &Connection,
+    student_id: u32,
+    unit_id: u32,
+    exercise_index: u32,
+    kind: &str,
+) {
+    db.execute(
+        "INSERT INTO exercise_notices (student_id, unit_id, exercise_index, kind) VALUES (?, ?, ?, ?)",
+        params![student_id, unit_id, exercise_index, kind],
+    )
+    .unwrap();
+}
+
+/// Lists the notices of a student in a unit along with the index of their
+/// exercise, oldest first.
+pub(super) fn notices_in_unit(
+    db: &Connection,
+    student_id: u32,
+    unit_id: u32,
+) -> Vec<(u32, Notice)> {
+    let mut stmt = db
+        .prepare("SELECT exercise_index, kind, strftime('%Y-%m-%dT%H:%M:%SZ', created_at) FROM exercise_notices WHERE student_id = ? AND unit_id = ? ORDER BY id")
+        .unwrap();
+    stmt.query_map(params![student_id, unit_id], |r| {
+        Ok((
+            r.get(0)?,
+            Notice {
+                kind: r.get(1)?,
+                created_at: r.get(2)?,
+            },
+        ))
+    })
+    .unwrap()
+    .map(|r| r.unwrap())
+    .collect()
+}
+
+/// Removes the notices of the student making the request about an exercise,
+/// once they read them.
+pub(crate) async fn dismiss_notices(
+    req: Request<Body>,
+    unit_id: u32,
+    exercise_index: u32,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    let principal = match authenticate(&req, db, config, "notice dismissal request").await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let db = db.lock().await;
+    db.execute(
+        "DELETE FROM exercise_notices WHERE student_id = ? AND unit_id = ? AND exercise_index = ?",
+        params![principal.student_id, unit_id, exercise_index],
+    )
+    .unwrap();
+
+    empty(StatusCode::OK)
+}
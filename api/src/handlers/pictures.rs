@@ -0,0 +1,322 @@
+use std::io::{ErrorKind, SeekFrom};
+
+use bytes::Bytes;
+use http::{header, StatusCode};
+use hyper::{Body, Request, Response};
+use rusqlite::Connection;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::Mutex,
+};
+
+use crate::config::Config;
+use crate::corrections::{self, is_valid_digest, GcError};
+use crate::http_helpers::*;
+
+/// The pictures are named after the digest of their content, so they never
+/// change and can be cached forever. They are private to the class though.
+const CACHE_CONTROL: &str = "private, max-age=31536000, immutable";
+
+/// The size of the chunks in which the pictures are streamed.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Whether an `If-None-Match` header matches the entity tag of a picture.
+/// Weak comparison is used, as required for this header.
+fn if_none_match(value: &str, etag: &str) -> bool {
+    value
+        .split(',')
+        .map(|t| t.trim())
+        .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+}
+
+/// Parses the value of a `Range` header for a file of length `len`, and
+/// returns the first and last byte of the range.
+///
+/// Multiple ranges are not supported, so they are ignored like malformed
+/// headers and the whole file is sent. `Err` means that the range cannot be
+/// satisfied.
+fn parse_range(value: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let spec = match value.trim().strip_prefix("bytes=") {
+        Some(val) if !val.contains(',') => val.trim(),
+        _ => return Ok(None),
+    };
+    let dash = match spec.find('-') {
+        Some(val) => val,
+        None => return Ok(None),
+    };
+    let (first, last) = (&spec[..dash], &spec[(dash + 1)..]);
+    let range = if first.is_empty() {
+        // A suffix range with the number of bytes at the end of the file.
+        let n: u64 = match last.parse() {
+            Ok(val) => val,
+            Err(_) => return Ok(None),
+        };
+        if n == 0 || len == 0 {
+            return Err(());
+        }
+        (len.saturating_sub(n), len - 1)
+    } else {
+        let first: u64 = match first.parse() {
+            Ok(val) => val,
+            Err(_) => return Ok(None),
+        };
+        let last: u64 = if last.is_empty() {
+            u64::MAX
+        } else {
+            match last.parse() {
+                Ok(val) => val,
+                Err(_) => return Ok(None),
+            }
+        };
+        if last < first {
+            return Ok(None);
+        }
+        if first >= len {
+            return Err(());
+        }
+        (first, last.min(len - 1))
+    };
+    Ok(Some(range))
+}
+
+/// Streams `len` bytes of a file from its current position.
+fn stream_file(file: File, len: u64) -> Body {
+    let stream = futures::stream::unfold((file, len), |(mut file, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+        let mut buf = vec![0; remaining.min(CHUNK_SIZE) as usize];
+        match file.read(&mut buf).await {
+            // The file is shorter than expected.
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), (file, remaining - n as u64)))
+            }
+            Err(err) => Some((Err(err), (file, 0))),
+        }
+    });
+    Body::wrap_stream(stream)
+}
+
+pub(crate) async fn correction_picture(
+    req: Request<Body>,
+    file_name: String,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    let digest = match file_name.strip_suffix(".png") {
+        Some(val) if is_valid_digest(val) => val,
+        _ => return empty(StatusCode::NOT_FOUND),
+    };
+    // Browsers cannot send an authorization header when loading an image, so
+    // a token that only grants access to this picture may be given in the URL
+    // instead.
+    let auth = match (get_bearer(&req), get_query_param(&req, "token")) {
+        (Some(bearer), _) => principal_from_token(bearer, db, config).await,
+        (None, Some(token)) => principal_from_picture_token(&token, digest, db, config).await,
+        (None, None) => Err(HttpAuthError::MissingBearer),
+    };
+    let principal = match auth {
+        Ok(val) => val,
+        Err(err) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!(
+                    "correction picture request with invalid authentication: {:?}",
+                    err
+                ),
+            );
+            return empty(StatusCode::FORBIDDEN);
+        }
+    };
+    // Only teachers can see the corrections in the trash.
+    if !principal.is_teacher() && !corrections::is_in_use(&*db.lock().await, digest) {
+        return empty(StatusCode::NOT_FOUND);
+    }
+    // A downscaled variant may be asked for with its width.
+    let width: Option<u32> = match get_query_param(&req, "size") {
+        Some(size) => match size.parse() {
+            Ok(val) if corrections::VARIANT_WIDTHS.contains(&val) => Some(val),
+            _ => return empty(StatusCode::BAD_REQUEST),
+        },
+        None => None,
+    };
+
+    let mut file = None;
+    let mut content_type = "image/png";
+    let mut etag = format!("\"{}\"", digest);
+    if let Some(width) = width {
+        let p = config
+            .corrections_path
+            .join(corrections::variant_file_name(digest, width));
+        match File::open(&p).await {
+            Ok(val) => {
+                file = Some(val);
+                content_type = "image/jpeg";
+                etag = format!("\"{}-{}\"", digest, width);
+            }
+            // The pictures uploaded before variants existed may not have
+            // them, so the original is served instead.
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => {
+                warn_for_req(
+                    &req,
+                    config,
+                    &format!("failed to open correction picture variant: {:?}", err),
+                );
+                return empty(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+
+    if let Some(v) = req.headers().get(header::IF_NONE_MATCH) {
+        if v.to_str().is_ok_and(|v| if_none_match(v, &etag)) {
+            return Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, &etag)
+                .header(header::CACHE_CONTROL, CACHE_CONTROL)
+                .body(Body::empty())
+                .unwrap();
+        }
+    }
+
+    let mut file = match file {
+        Some(val) => val,
+        None => {
+            let p = config.corrections_path.join(&file_name);
+            match File::open(&p).await {
+                Ok(val) => val,
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    return empty(StatusCode::NOT_FOUND)
+                }
+                Err(err) => {
+                    warn_for_req(
+                        &req,
+                        config,
+                        &format!("failed to open correction picture: {:?}", err),
+                    );
+                    return empty(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            }
+        }
+    };
+    let len = match file.metadata().await {
+        Ok(val) => val.len(),
+        Err(err) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!("failed to read correction picture metadata: {:?}", err),
+            );
+            return empty(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    // A range is only honoured if the picture did not change since the
+    // client got the first part, which is always the case here unless the
+    // client asks for another picture.
+    let if_range_ok = req
+        .headers()
+        .get(header::IF_RANGE)
+        .is_none_or(|v| v.to_str().is_ok_and(|v| v == etag));
+    let range = match req.headers().get(header::RANGE) {
+        Some(v) if if_range_ok => match v.to_str().map(|v| parse_range(v, len)) {
+            Ok(Ok(val)) => val,
+            Ok(Err(())) => {
+                return Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                    .body(Body::empty())
+                    .unwrap();
+            }
+            Err(_) => None,
+        },
+        _ => None,
+    };
+
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, CACHE_CONTROL)
+        .header(header::ACCEPT_RANGES, "bytes");
+    let (builder, body_len) = match range {
+        Some((first, last)) => {
+            if let Err(err) = file.seek(SeekFrom::Start(first)).await {
+                warn_for_req(
+                    &req,
+                    config,
+                    &format!("failed to seek in correction picture: {:?}", err),
+                );
+                return empty(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+            let builder = builder.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", first, last, len),
+            );
+            (builder, last - first + 1)
+        }
+        None => (builder.status(StatusCode::OK), len),
+    };
+
+    builder
+        .header(header::CONTENT_LENGTH, body_len)
+        .body(stream_file(file, body_len))
+        .unwrap()
+}
+
+pub(crate) async fn collect_correction_garbage(
+    req: Request<Body>,
+    db: &Mutex<Connection>,
+    pictures_lock: &Mutex<()>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "correction garbage collection request";
+    if let Err(res) = authenticate_teacher(&req, db, config, WHAT).await {
+        return res;
+    }
+    let options: corrections::GcOptions = match parse_query(&req, config, WHAT) {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    match corrections::collect_garbage(
+        db,
+        pictures_lock,
+        &config.corrections_path,
+        config.corrections_trash_retention,
+        options,
+    )
+    .await
+    {
+        Ok(report) => {
+            corrections::log_gc_report(&report);
+            json(&report, StatusCode::OK)
+        }
+        Err(GcError::NoCorrections { pictures }) => json(
+            &serde_json::json!({ "error": "noCorrections", "pictures": pictures }),
+            StatusCode::CONFLICT,
+        ),
+        Err(GcError::TooManyUnreferenced {
+            unreferenced,
+            pictures,
+        }) => json(
+            &serde_json::json!({
+                "error": "tooManyUnreferenced",
+                "unreferenced": unreferenced,
+                "pictures": pictures,
+            }),
+            StatusCode::CONFLICT,
+        ),
+        Err(GcError::Io(err)) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!("failed to collect unused correction pictures: {:?}", err),
+            );
+            empty(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
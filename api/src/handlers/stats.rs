@@ -0,0 +1,175 @@
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
+use http::StatusCode;
+use hyper::{Body, Request, Response};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use super::Student;
+use crate::config::Config;
+use crate::http_helpers::*;
+
+#[derive(Serialize, Default)]
+struct Counts {
+    /// The exercises that are reserved and not presented yet.
+    reservations: u32,
+    presentations: u32,
+}
+
+#[derive(Serialize)]
+struct UnitCounts {
+    #[serde(rename = "unitId")]
+    unit_id: u32,
+    #[serde(flatten)]
+    counts: Counts,
+}
+
+#[derive(Serialize)]
+struct StudentStats {
+    #[serde(flatten)]
+    student: Student,
+    #[serde(flatten)]
+    counts: Counts,
+    /// The day of the last presentation of the student, in the `YYYY-MM-DD`
+    /// format. The presentations from before their time was recorded count as
+    /// made on the correction day of their unit.
+    #[serde(rename = "lastPresentation")]
+    last_presentation: Option<String>,
+    units: Vec<UnitCounts>,
+}
+
+#[derive(Serialize)]
+struct ClassStats {
+    students: Vec<StudentStats>,
+    #[serde(rename = "groupEven")]
+    group_even: Counts,
+    #[serde(rename = "groupOdd")]
+    group_odd: Counts,
+}
+
+/// Reads the statistics of the students of the class, or of a single student
+/// if `student_id` is given. The students without any reservation are
+/// included, but not the deactivated ones.
+fn read_student_stats(
+    db: &Connection,
+    student_id: Option<u32>,
+    time_zone: Tz,
+) -> Vec<StudentStats> {
+    let mut stmt = db
+        .prepare(
+            "SELECT s.id, s.username, s.full_name, s.in_group_even, e.unit_id, \
+             COALESCE(SUM(e.state = 0), 0), COALESCE(SUM(e.state = 1), 0), \
+             CAST(strftime('%s', MAX(CASE WHEN e.state = 1 THEN e.presented_at END)) AS INTEGER), \
+             MAX(CASE WHEN e.state = 1 AND e.presented_at IS NULL THEN CASE WHEN s.in_group_even THEN u.deadline_group_even ELSE u.deadline_group_odd END END) \
+             FROM students s \
+             LEFT JOIN exercise_student_state e ON e.student_id = s.id \
+             LEFT JOIN units u ON e.unit_id = u.id \
+             WHERE (?1 IS NULL AND s.role = 0 AND s.active) OR s.id = ?1 \
+             GROUP BY s.id, e.unit_id \
+             ORDER BY s.username, e.unit_id",
+        )
+        .unwrap();
+    let mut rows = stmt.query(params![student_id]).unwrap();
+    let mut result: Vec<StudentStats> = Vec::new();
+    while let Some(r) = rows.next().unwrap() {
+        let id: u32 = r.get(0).unwrap();
+        if result.last().map(|s| s.student.id) != Some(id) {
+            result.push(StudentStats {
+                student: Student {
+                    id,
+                    username: r.get(1).unwrap(),
+                    full_name: r.get(2).unwrap(),
+                    in_group_even: r.get(3).unwrap(),
+                },
+                counts: Counts::default(),
+                last_presentation: None,
+                units: Vec::new(),
+            });
+        }
+        let stats = result.last_mut().unwrap();
+        // The row of a student without any reservation has no unit.
+        let unit_id: Option<u32> = r.get(4).unwrap();
+        let unit_id = match unit_id {
+            Some(val) => val,
+            None => continue,
+        };
+        let counts = Counts {
+            reservations: r.get(5).unwrap(),
+            presentations: r.get(6).unwrap(),
+        };
+        let presented_at: Option<i64> = r.get(7).unwrap();
+        let presented_on = presented_at
+            .and_then(|t| Utc.timestamp_opt(t, 0).single())
+            .map(|t| t.with_timezone(&time_zone).format("%Y-%m-%d").to_string());
+        let correction_day: Option<String> = r.get(8).unwrap();
+        let last_presentation = presented_on.max(correction_day);
+        stats.counts.reservations += counts.reservations;
+        stats.counts.presentations += counts.presentations;
+        if last_presentation > stats.last_presentation {
+            stats.last_presentation = last_presentation;
+        }
+        stats.units.push(UnitCounts { unit_id, counts });
+    }
+    result
+}
+
+/// Returns how many exercises a student reserved and presented, in total and
+/// in each unit. Students can only see their own statistics.
+pub(crate) async fn student_stats(
+    req: Request<Body>,
+    student_id: u32,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "student statistics request";
+    let principal = match authenticate(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    if !principal.is_teacher() && principal.student_id != student_id {
+        warn_for_req(&req, config, &format!("{} from another student", WHAT));
+        return empty(StatusCode::FORBIDDEN);
+    }
+
+    let db = db.lock().await;
+    match read_student_stats(&db, Some(student_id), config.deadline_time_zone).pop() {
+        Some(stats) => json(&stats, StatusCode::OK),
+        None => empty(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Returns the statistics of every active student of the class, to spread the
+/// presentations fairly, and the totals of each group.
+pub(crate) async fn class_stats(
+    req: Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    if let Err(res) = authenticate_teacher(&req, db, config, "class statistics request").await {
+        return res;
+    }
+
+    let db = db.lock().await;
+    let students = read_student_stats(&db, None, config.deadline_time_zone);
+    let mut group_even = Counts::default();
+    let mut group_odd = Counts::default();
+    for s in &students {
+        let group = if s.student.in_group_even {
+            &mut group_even
+        } else {
+            &mut group_odd
+        };
+        group.reservations += s.counts.reservations;
+        group.presentations += s.counts.presentations;
+    }
+
+    json(
+        &ClassStats {
+            students,
+            group_even,
+            group_odd,
+        },
+        StatusCode::OK,
+    )
+}
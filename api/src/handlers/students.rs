@@ -0,0 +1,554 @@
+use http::StatusCode;
+use hyper::{Body, Request, Response};
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{exercise_has_room, get_unit, promote_waitlisted};
+use crate::audit;
+use crate::config::Config;
+use crate::http_helpers::*;
+use crate::passwords;
+use crate::roster::{self, is_valid_email, is_valid_full_name, is_valid_username};
+use crate::sessions::{self, Role};
+
+/// A student as seen by the teachers, with the fields that are only useful to
+/// manage the roster.
+#[derive(Serialize)]
+struct RosterEntry {
+    id: u32,
+    username: String,
+    #[serde(rename = "fullName")]
+    full_name: String,
+    #[serde(rename = "inGroupEven")]
+    in_group_even: bool,
+    role: Role,
+    active: bool,
+    email: Option<String>,
+    /// Whether the student chose their own password instead of using the
+    /// shared one.
+    #[serde(rename = "hasPassword")]
+    has_password: bool,
+}
+
+#[derive(Deserialize)]
+struct CreateStudentRequest {
+    username: String,
+    #[serde(rename = "fullName")]
+    full_name: String,
+    #[serde(rename = "inGroupEven")]
+    in_group_even: bool,
+    #[serde(default)]
+    role: Role,
+    email: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PatchStudentRequest {
+    username: Option<String>,
+    #[serde(rename = "fullName")]
+    full_name: Option<String>,
+    #[serde(rename = "inGroupEven")]
+    in_group_even: Option<bool>,
+    role: Option<Role>,
+    active: Option<bool>,
+    /// An empty string removes the email address.
+    email: Option<String>,
+    /// Forgets the password chosen by the student so that they can log in
+    /// with the shared password again. They need a setup code to choose a new
+    /// one, see `issue_password_setup_code`.
+    #[serde(rename = "resetPassword", default)]
+    reset_password: bool,
+}
+
+#[derive(Deserialize)]
+struct DeleteStudentQuery {
+    /// Also delete the reservations of the student and move their corrections
+    /// to the trash, instead of refusing to delete a student who has some.
+    #[serde(default)]
+    cascade: bool,
+}
+
+fn get_roster_entry(db: &Connection, student_id: u32) -> Option<RosterEntry> {
+    db.query_row(
+        "SELECT id, username, full_name, in_group_even, role, active, email, password_hash IS NOT NULL FROM students WHERE id = ?",
+        params![student_id],
+        |r| {
+            Ok(RosterEntry {
+                id: r.get(0)?,
+                username: r.get(1)?,
+                full_name: r.get(2)?,
+                in_group_even: r.get(3)?,
+                role: r.get(4)?,
+                active: r.get(5)?,
+                email: r.get(6)?,
+                has_password: r.get(7)?,
+            })
+        },
+    )
+    .optional()
+    .unwrap()
+}
+
+fn username_taken(db: &Connection, username: &str, except: Option<u32>) -> bool {
+    db.query_row(
+        "SELECT EXISTS (SELECT 1 FROM students WHERE username = ? AND id IS NOT ?)",
+        params![username, except],
+        |r| r.get(0),
+    )
+    .unwrap()
+}
+
+pub(crate) async fn list_students(
+    req: Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    if let Err(res) = authenticate_teacher(&req, db, config, "student list request").await {
+        return res;
+    }
+
+    let db = db.lock().await;
+    let mut stmt = db
+        .prepare("SELECT id, username, full_name, in_group_even, role, active, email, password_hash IS NOT NULL FROM students ORDER BY username")
+        .unwrap();
+    let result: Vec<RosterEntry> = stmt
+        .query_map(NO_PARAMS, |r| {
+            Ok(RosterEntry {
+                id: r.get(0)?,
+                username: r.get(1)?,
+                full_name: r.get(2)?,
+                in_group_even: r.get(3)?,
+                role: r.get(4)?,
+                active: r.get(5)?,
+                email: r.get(6)?,
+                has_password: r.get(7)?,
+            })
+        })
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+
+    json(&result, StatusCode::OK)
+}
+
+pub(crate) async fn create_student(
+    mut req: Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "student creation request";
+    let principal = match authenticate_teacher(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    let r: CreateStudentRequest = match read_json_body(&mut req, 4096, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    if !is_valid_username(&r.username)
+        || !is_valid_full_name(&r.full_name)
+        || !r.email.as_deref().is_none_or(is_valid_email)
+    {
+        warn_for_req(&req, config, "student creation request with invalid fields");
+        return empty(StatusCode::BAD_REQUEST);
+    }
+
+    let db = db.lock().await;
+    if username_taken(&db, &r.username, None) {
+        return json(
+            &serde_json::json!({ "error": "usernameTaken" }),
+            StatusCode::CONFLICT,
+        );
+    }
+    db.execute(
+        "INSERT INTO students (username, full_name, in_group_even, role, email) VALUES (?, ?, ?, ?, ?)",
+        params![r.username, r.full_name, r.in_group_even, r.role, r.email],
+    )
+    .unwrap();
+
+    let student = get_roster_entry(&db, db.last_insert_rowid() as u32).unwrap();
+    audit::record_for_req(
+        &db,
+        &req,
+        config,
+        principal.student_id,
+        audit::Event::new(audit::Action::CreateStudent).new_value(&student),
+    );
+    json(&student, StatusCode::CREATED)
+}
+
+pub(crate) async fn patch_student(
+    mut req: Request<Body>,
+    student_id: u32,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "student patch request";
+    let principal = match authenticate_teacher(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    let r: PatchStudentRequest = match read_json_body(&mut req, 4096, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let db = db.lock().await;
+    let mut student = match get_roster_entry(&db, student_id) {
+        Some(val) => val,
+        None => return empty(StatusCode::NOT_FOUND),
+    };
+    let old_student = serde_json::to_value(&student).unwrap();
+    let old_role = student.role;
+    let was_active = student.active;
+    let was_in_group_even = student.in_group_even;
+
+    if let Some(username) = r.username {
+        student.username = username;
+    }
+    if let Some(full_name) = r.full_name {
+        student.full_name = full_name;
+    }
+    if let Some(in_group_even) = r.in_group_even {
+        student.in_group_even = in_group_even;
+    }
+    if let Some(role) = r.role {
+        student.role = role;
+    }
+    if let Some(active) = r.active {
+        student.active = active;
+    }
+    if let Some(email) = r.email {
+        student.email = Some(email).filter(|e| !e.is_empty());
+    }
+    if !is_valid_username(&student.username)
+        || !is_valid_full_name(&student.full_name)
+        || !student.email.as_deref().is_none_or(is_valid_email)
+    {
+        warn_for_req(&req, config, "student patch request with invalid fields");
+        return empty(StatusCode::BAD_REQUEST);
+    }
+    if username_taken(&db, &student.username, Some(student_id)) {
+        return json(
+            &serde_json::json!({ "error": "usernameTaken" }),
+            StatusCode::CONFLICT,
+        );
+    }
+
+    let tx = db.unchecked_transaction().unwrap();
+    tx.execute(
+        "UPDATE students SET username = ?, full_name = ?, in_group_even = ?, role = ?, active = ?, email = ? WHERE id = ?",
+        params![
+            student.username,
+            student.full_name,
+            student.in_group_even,
+            student.role,
+            student.active,
+            student.email,
+            student_id
+        ],
+    )
+    .unwrap();
+    if r.reset_password {
+        tx.execute(
+            "UPDATE students SET password_hash = NULL WHERE id = ?",
+            params![student_id],
+        )
+        .unwrap();
+        student.has_password = false;
+    }
+    // The tokens of the student carry their role, and a deactivated student
+    // must not stay logged in.
+    if r.reset_password || student.role != old_role || (was_active && !student.active) {
+        sessions::revoke_all_for_student(&tx, student_id, None);
+    }
+    // With a limit of reservers per group, the reservations of a student who
+    // changes group take room in the new group and leave some in the old one.
+    if student.in_group_even != was_in_group_even {
+        let reserved: Vec<(u32, u32)> = tx
+            .prepare(
+                "SELECT unit_id, exercise_index FROM exercise_student_state WHERE student_id = ? AND state = 0",
+            )
+            .unwrap()
+            .query_map(params![student_id], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        for (unit_id, exercise_index) in reserved {
+            let unit = match get_unit(&tx, unit_id) {
+                Some(val) if val.max_reservers_per_group => val,
+                _ => continue,
+            };
+            if !exercise_has_room(&tx, &unit, exercise_index, student_id) {
+                return json(
+                    &serde_json::json!({
+                        "error": "exerciseFull",
+                        "unitId": unit_id,
+                        "exerciseIndex": exercise_index,
+                        "limit": unit.max_reservers_per_exercise,
+                        "perGroup": true,
+                    }),
+                    StatusCode::CONFLICT,
+                );
+            }
+            promote_waitlisted(
+                &tx,
+                &req,
+                config,
+                principal.student_id,
+                &unit,
+                exercise_index,
+            );
+        }
+    }
+    audit::record_for_req(
+        &tx,
+        &req,
+        config,
+        principal.student_id,
+        audit::Event::new(audit::Action::PatchStudent)
+            .old_value(&old_student)
+            .new_value(&student),
+    );
+    tx.commit().unwrap();
+
+    json(&student, StatusCode::OK)
+}
+
+#[derive(Serialize)]
+struct IssuedSetupCode {
+    code: String,
+    /// In RFC 3339 format.
+    #[serde(rename = "expiresAt")]
+    expires_at: String,
+}
+
+/// Gives a student a one-time code to choose their password with, replacing
+/// the previous one. Only the teacher sees the code, and has to give it to the
+/// student.
+pub(crate) async fn issue_password_setup_code(
+    req: Request<Body>,
+    student_id: u32,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "password setup code request";
+    let principal = match authenticate_teacher(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let setup_code = tokio::task::spawn_blocking(passwords::generate_setup_code)
+        .await
+        .expect("failed to generate password setup code");
+
+    let db = db.lock().await;
+    let username: String = match db
+        .query_row(
+            "SELECT username FROM students WHERE id = ?",
+            params![student_id],
+            |r| r.get(0),
+        )
+        .optional()
+        .unwrap()
+    {
+        Some(val) => val,
+        None => return empty(StatusCode::NOT_FOUND),
+    };
+    let expires_at = passwords::save_setup_code(&db, student_id, &setup_code);
+    // The code itself must not end up in the log.
+    audit::record_for_req(
+        &db,
+        &req,
+        config,
+        principal.student_id,
+        audit::Event::new(audit::Action::IssuePasswordSetupCode)
+            .new_value(&serde_json::json!({ "username": username, "expiresAt": expires_at })),
+    );
+
+    json(
+        &IssuedSetupCode {
+            code: setup_code.code,
+            expires_at,
+        },
+        StatusCode::OK,
+    )
+}
+
+pub(crate) async fn delete_student(
+    req: Request<Body>,
+    student_id: u32,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "student deletion request";
+    let principal = match authenticate_teacher(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    let q: DeleteStudentQuery = match parse_query(&req, config, WHAT) {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let db = db.lock().await;
+    let student = match get_roster_entry(&db, student_id) {
+        Some(val) => val,
+        None => return empty(StatusCode::NOT_FOUND),
+    };
+
+    let (states, corrections): (u32, u32) = db
+        .query_row(
+            "SELECT (SELECT COUNT(*) FROM exercise_student_state WHERE student_id = ?1), (SELECT COUNT(*) FROM exercise_corrections WHERE created_by = ?1)",
+            params![student_id],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .unwrap();
+    if (states > 0 || corrections > 0) && !q.cascade {
+        return json(
+            &serde_json::json!({
+                "error": "studentHasHistory",
+                "exerciseStates": states,
+                "corrections": corrections,
+            }),
+            StatusCode::CONFLICT,
+        );
+    }
+
+    // The reservations of the student go to the students who wait for them.
+    let reserved: Vec<(u32, u32)> = db
+        .prepare(
+            "SELECT unit_id, exercise_index FROM exercise_student_state WHERE student_id = ? AND state = 0",
+        )
+        .unwrap()
+        .query_map(params![student_id], |r| Ok((r.get(0)?, r.get(1)?)))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    let published: Vec<(u32, u32, String)> = db
+        .prepare(
+            "SELECT unit_id, unit_exercise, picture_digest FROM exercise_corrections WHERE created_by = ? AND deleted_at IS NULL",
+        )
+        .unwrap()
+        .query_map(params![student_id], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+
+    let tx = db.unchecked_transaction().unwrap();
+    tx.execute(
+        "DELETE FROM exercise_student_state WHERE student_id = ?",
+        params![student_id],
+    )
+    .unwrap();
+    // The corrections of the student go to the trash, from which a teacher
+    // can restore them until they are purged. They no longer refer to the
+    // student, whose id may be given to a new student.
+    tx.execute(
+        "UPDATE exercise_corrections SET deleted_at = CURRENT_TIMESTAMP, deleted_by = ? WHERE created_by = ? AND deleted_at IS NULL",
+        params![principal.student_id, student_id],
+    )
+    .unwrap();
+    tx.execute(
+        "UPDATE exercise_corrections SET created_by = NULL WHERE created_by = ?",
+        params![student_id],
+    )
+    .unwrap();
+    // The corrections of others that the student put in the trash stay there.
+    tx.execute(
+        "UPDATE exercise_corrections SET deleted_by = NULL WHERE deleted_by = ?",
+        params![student_id],
+    )
+    .unwrap();
+    tx.execute(
+        "DELETE FROM exercise_notices WHERE student_id = ?",
+        params![student_id],
+    )
+    .unwrap();
+    tx.execute(
+        "DELETE FROM sessions WHERE student_id = ?",
+        params![student_id],
+    )
+    .unwrap();
+    tx.execute("DELETE FROM students WHERE id = ?", params![student_id])
+        .unwrap();
+    audit::record_for_req(
+        &tx,
+        &req,
+        config,
+        principal.student_id,
+        audit::Event::new(audit::Action::DeleteStudent).old_value(&student),
+    );
+    for (unit_id, exercise_index, digest) in published {
+        audit::record_for_req(
+            &tx,
+            &req,
+            config,
+            principal.student_id,
+            audit::Event::new(audit::Action::DeleteCorrection)
+                .exercise(unit_id, exercise_index)
+                .old_value(&digest),
+        );
+    }
+    for (unit_id, exercise_index) in reserved {
+        if let Some(unit) = get_unit(&tx, unit_id) {
+            promote_waitlisted(
+                &tx,
+                &req,
+                config,
+                principal.student_id,
+                &unit,
+                exercise_index,
+            );
+        }
+    }
+    tx.commit().unwrap();
+
+    empty(StatusCode::OK)
+}
+
+pub(crate) async fn import_students(
+    mut req: Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    let principal = match authenticate_teacher(&req, db, config, "roster import request").await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let b = match collect_body(req.body_mut(), 1024 * 1024).await {
+        Ok(val) => val,
+        Err(CollectBodyError::ReadError(err)) => {
+            warn_for_req(
+                &req,
+                config,
+                &format!("failed to read body from roster import request: {:?}", err),
+            );
+            return empty(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        Err(CollectBodyError::TooLarge) => {
+            warn_for_req(&req, config, "roster import request body is too large");
+            return empty(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+    };
+
+    let db = db.lock().await;
+    match roster::import_csv(&db, &b) {
+        Ok(summary) => {
+            audit::record_for_req(
+                &db,
+                &req,
+                config,
+                principal.student_id,
+                audit::Event::new(audit::Action::ImportStudents).new_value(&summary),
+            );
+            json(&summary, StatusCode::OK)
+        }
+        Err(errors) => json(
+            &serde_json::json!({ "errors": errors }),
+            StatusCode::BAD_REQUEST,
+        ),
+    }
+}
@@ -0,0 +1,154 @@
+use http::StatusCode;
+use hyper::{Body, Request, Response};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use super::Student;
+use crate::audit;
+use crate::config::Config;
+use crate::corrections;
+use crate::http_helpers::*;
+
+#[derive(Serialize)]
+struct TrashedCorrection {
+    #[serde(rename = "unitId")]
+    unit_id: u32,
+    #[serde(rename = "exerciseIndex")]
+    exercise_index: u32,
+    digest: String,
+    /// The student is missing if their account was deleted since.
+    #[serde(rename = "createdBy")]
+    created_by: Option<Student>,
+    /// When the picture was moved to the trash, in RFC 3339 format.
+    #[serde(rename = "deletedAt")]
+    deleted_at: String,
+    /// The student is missing if their account was deleted since.
+    #[serde(rename = "deletedBy")]
+    deleted_by: Option<Student>,
+}
+
+/// Lists the corrections in the trash that can still be restored. Students
+/// only see the ones that they uploaded or deleted.
+pub(crate) async fn trashed_corrections(
+    req: Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "list trashed corrections request";
+    let principal = match authenticate(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let db = db.lock().await;
+    let mut stmt = db
+        .prepare(
+            "SELECT c.unit_id, c.unit_exercise, c.picture_digest, strftime('%Y-%m-%dT%H:%M:%SZ', c.deleted_at), \
+             a.id, a.username, a.full_name, a.in_group_even, \
+             d.id, d.username, d.full_name, d.in_group_even \
+             FROM exercise_corrections c \
+             LEFT JOIN students a ON c.created_by = a.id \
+             LEFT JOIN students d ON c.deleted_by = d.id \
+             WHERE c.deleted_at IS NOT NULL AND c.deleted_at >= datetime('now', ?1) \
+             AND (?2 OR c.created_by = ?3 OR c.deleted_by = ?3) \
+             ORDER BY c.deleted_at DESC",
+        )
+        .unwrap();
+    let mut rows = stmt
+        .query(params![
+            corrections::sqlite_age(config.corrections_trash_retention),
+            principal.is_teacher(),
+            principal.student_id
+        ])
+        .unwrap();
+    let mut result = Vec::new();
+    while let Some(r) = rows.next().unwrap() {
+        let created_by_id: Option<u32> = r.get(4).unwrap();
+        let deleted_by_id: Option<u32> = r.get(8).unwrap();
+        result.push(TrashedCorrection {
+            unit_id: r.get(0).unwrap(),
+            exercise_index: r.get(1).unwrap(),
+            digest: r.get(2).unwrap(),
+            deleted_at: r.get(3).unwrap(),
+            created_by: created_by_id.map(|id| Student {
+                id,
+                username: r.get(5).unwrap(),
+                full_name: r.get(6).unwrap(),
+                in_group_even: r.get(7).unwrap(),
+            }),
+            deleted_by: deleted_by_id.map(|id| Student {
+                id,
+                username: r.get(9).unwrap(),
+                full_name: r.get(10).unwrap(),
+                in_group_even: r.get(11).unwrap(),
+            }),
+        });
+    }
+
+    json(&result, StatusCode::OK)
+}
+
+/// Takes a correction out of the trash, unless it has been there for longer
+/// than the retention period. Students can only restore the corrections that
+/// they uploaded or deleted.
+pub(crate) async fn restore_exercise_correction(
+    req: Request<Body>,
+    unit_id: u32,
+    exercise_index: u32,
+    correction_digest: String,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "exercise correction restoration request";
+    let principal = match authenticate(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let db = db.lock().await;
+    let age = corrections::sqlite_age(config.corrections_trash_retention);
+    let owners: Option<(Option<u32>, Option<u32>)> = db
+        .query_row(
+            "SELECT created_by, deleted_by FROM exercise_corrections WHERE unit_id = ? AND unit_exercise = ? AND picture_digest = ? AND deleted_at IS NOT NULL AND deleted_at >= datetime('now', ?)",
+            params![unit_id, exercise_index, correction_digest, age],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()
+        .unwrap();
+    let (created_by, deleted_by) = match owners {
+        Some(val) => val,
+        None => return empty(StatusCode::NOT_FOUND),
+    };
+    if !principal.is_teacher()
+        && created_by != Some(principal.student_id)
+        && deleted_by != Some(principal.student_id)
+    {
+        warn_for_req(
+            &req,
+            config,
+            &format!(
+                "{} from a student who neither uploaded nor deleted it",
+                WHAT
+            ),
+        );
+        return empty(StatusCode::FORBIDDEN);
+    }
+
+    db.execute(
+        "UPDATE exercise_corrections SET deleted_at = NULL, deleted_by = NULL WHERE unit_id = ? AND unit_exercise = ? AND picture_digest = ?",
+        params![unit_id, exercise_index, correction_digest],
+    )
+    .unwrap();
+    audit::record_for_req(
+        &db,
+        &req,
+        config,
+        principal.student_id,
+        audit::Event::new(audit::Action::RestoreCorrection)
+            .exercise(unit_id, exercise_index)
+            .new_value(&correction_digest),
+    );
+
+    empty(StatusCode::OK)
+}
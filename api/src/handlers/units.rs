@@ -0,0 +1,325 @@
+use chrono::NaiveDate;
+use http::StatusCode;
+use hyper::{Body, Request, Response};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Deserializer};
+use tokio::sync::Mutex;
+
+use super::{get_unit, promote_waitlisted, Unit};
+use crate::audit;
+use crate::config::Config;
+use crate::http_helpers::*;
+
+/// The maximum number of exercises in a unit. It only guards against typos,
+/// since every exercise is sent to the front end.
+const MAX_EXERCISE_COUNT: u32 = 100;
+
+const MAX_UNIT_NAME_LEN: usize = 200;
+
+#[derive(Deserialize)]
+struct CreateUnitRequest {
+    name: String,
+    #[serde(rename = "exerciseCount")]
+    exercise_count: u32,
+    #[serde(rename = "deadlineGroupEven")]
+    deadline_group_even: String,
+    #[serde(rename = "deadlineGroupOdd")]
+    deadline_group_odd: String,
+    #[serde(rename = "maxReserversPerExercise")]
+    max_reservers_per_exercise: Option<u32>,
+    #[serde(rename = "maxReserversPerGroup", default)]
+    max_reservers_per_group: bool,
+    #[serde(rename = "maxReservationsPerStudent")]
+    max_reservations_per_student: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct PatchUnitRequest {
+    name: Option<String>,
+    #[serde(rename = "exerciseCount")]
+    exercise_count: Option<u32>,
+    #[serde(rename = "deadlineGroupEven")]
+    deadline_group_even: Option<String>,
+    #[serde(rename = "deadlineGroupOdd")]
+    deadline_group_odd: Option<String>,
+    /// `null` removes the limit.
+    #[serde(
+        rename = "maxReserversPerExercise",
+        default,
+        deserialize_with = "deserialize_nullable"
+    )]
+    max_reservers_per_exercise: Option<Option<u32>>,
+    #[serde(rename = "maxReserversPerGroup")]
+    max_reservers_per_group: Option<bool>,
+    /// `null` removes the limit.
+    #[serde(
+        rename = "maxReservationsPerStudent",
+        default,
+        deserialize_with = "deserialize_nullable"
+    )]
+    max_reservations_per_student: Option<Option<u32>>,
+}
+
+/// Tells a field that is `null` apart from a field that is missing, which
+/// `serde` both turns into `None` otherwise.
+fn deserialize_nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// Checks that a deadline is a valid date in the `YYYY-MM-DD` format, which
+/// is the format that the front end expects.
+fn is_valid_deadline(s: &str) -> bool {
+    match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        // Reject dates such as "2021-3-1" that chrono accepts.
+        Ok(date) => date.format("%Y-%m-%d").to_string() == s,
+        Err(_) => false,
+    }
+}
+
+fn is_valid_unit(unit: &Unit) -> bool {
+    !unit.name.trim().is_empty()
+        && unit.name.len() <= MAX_UNIT_NAME_LEN
+        && unit.exercise_count > 0
+        && unit.exercise_count <= MAX_EXERCISE_COUNT
+        && is_valid_deadline(&unit.deadline_group_even)
+        && is_valid_deadline(&unit.deadline_group_odd)
+        && unit.max_reservers_per_exercise != Some(0)
+        && unit.max_reservations_per_student != Some(0)
+}
+
+/// Returns the highest index of an exercise in the unit that was reserved,
+/// presented or that has a correction.
+fn highest_used_exercise(db: &Connection, unit_id: u32) -> Option<u32> {
+    db.query_row(
+        "SELECT MAX(i) FROM (SELECT exercise_index AS i FROM exercise_student_state WHERE unit_id = ?1 UNION ALL SELECT unit_exercise FROM exercise_corrections WHERE unit_id = ?1)",
+        params![unit_id],
+        |r| r.get(0),
+    )
+    .unwrap()
+}
+
+pub(crate) async fn create_unit(
+    mut req: Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "unit creation request";
+    let principal = match authenticate_teacher(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    let r: CreateUnitRequest = match read_json_body(&mut req, 4096, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let mut unit = Unit {
+        id: 0,
+        name: r.name,
+        exercise_count: r.exercise_count,
+        deadline_group_even: r.deadline_group_even,
+        deadline_group_odd: r.deadline_group_odd,
+        max_reservers_per_exercise: r.max_reservers_per_exercise,
+        max_reservers_per_group: r.max_reservers_per_group,
+        max_reservations_per_student: r.max_reservations_per_student,
+    };
+    if !is_valid_unit(&unit) {
+        warn_for_req(&req, config, "unit creation request with invalid fields");
+        return empty(StatusCode::BAD_REQUEST);
+    }
+
+    let db = db.lock().await;
+    db.execute(
+        "INSERT INTO units (name, exercise_count, deadline_group_even, deadline_group_odd, max_reservers_per_exercise, max_reservers_per_group, max_reservations_per_student) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params![
+            unit.name,
+            unit.exercise_count,
+            unit.deadline_group_even,
+            unit.deadline_group_odd,
+            unit.max_reservers_per_exercise,
+            unit.max_reservers_per_group,
+            unit.max_reservations_per_student
+        ],
+    )
+    .unwrap();
+    unit.id = db.last_insert_rowid() as u32;
+    audit::record_for_req(
+        &db,
+        &req,
+        config,
+        principal.student_id,
+        audit::Event::new(audit::Action::CreateUnit)
+            .unit(unit.id)
+            .new_value(&unit),
+    );
+
+    json(&unit, StatusCode::CREATED)
+}
+
+pub(crate) async fn patch_unit(
+    mut req: Request<Body>,
+    unit_id: u32,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "unit patch request";
+    let principal = match authenticate_teacher(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+    let r: PatchUnitRequest = match read_json_body(&mut req, 4096, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let db = db.lock().await;
+    let mut unit = match get_unit(&db, unit_id) {
+        Some(val) => val,
+        None => return empty(StatusCode::NOT_FOUND),
+    };
+    let old_unit = serde_json::to_value(&unit).unwrap();
+    if let Some(name) = r.name {
+        unit.name = name;
+    }
+    if let Some(exercise_count) = r.exercise_count {
+        unit.exercise_count = exercise_count;
+    }
+    if let Some(deadline) = r.deadline_group_even {
+        unit.deadline_group_even = deadline;
+    }
+    if let Some(deadline) = r.deadline_group_odd {
+        unit.deadline_group_odd = deadline;
+    }
+    if let Some(max) = r.max_reservers_per_exercise {
+        unit.max_reservers_per_exercise = max;
+    }
+    if let Some(per_group) = r.max_reservers_per_group {
+        unit.max_reservers_per_group = per_group;
+    }
+    if let Some(max) = r.max_reservations_per_student {
+        unit.max_reservations_per_student = max;
+    }
+    if !is_valid_unit(&unit) {
+        warn_for_req(&req, config, "unit patch request with invalid fields");
+        return empty(StatusCode::BAD_REQUEST);
+    }
+
+    // Exercises that students already worked on cannot disappear.
+    if let Some(used) = highest_used_exercise(&db, unit_id) {
+        if used >= unit.exercise_count {
+            return json(
+                &serde_json::json!({
+                    "error": "exerciseInUse",
+                    "exerciseIndex": used,
+                }),
+                StatusCode::CONFLICT,
+            );
+        }
+    }
+
+    let tx = db.unchecked_transaction().unwrap();
+    tx.execute(
+        "UPDATE units SET name = ?, exercise_count = ?, deadline_group_even = ?, deadline_group_odd = ?, max_reservers_per_exercise = ?, max_reservers_per_group = ?, max_reservations_per_student = ? WHERE id = ?",
+        params![
+            unit.name,
+            unit.exercise_count,
+            unit.deadline_group_even,
+            unit.deadline_group_odd,
+            unit.max_reservers_per_exercise,
+            unit.max_reservers_per_group,
+            unit.max_reservations_per_student,
+            unit_id
+        ],
+    )
+    .unwrap();
+    // The flags and the notices of the exercises that were removed are not worth keeping.
+    tx.execute(
+        "DELETE FROM exercise WHERE unit_id = ? AND index_ >= ?",
+        params![unit_id, unit.exercise_count],
+    )
+    .unwrap();
+    tx.execute(
+        "DELETE FROM exercise_notices WHERE unit_id = ? AND exercise_index >= ?",
+        params![unit_id, unit.exercise_count],
+    )
+    .unwrap();
+    audit::record_for_req(
+        &tx,
+        &req,
+        config,
+        principal.student_id,
+        audit::Event::new(audit::Action::PatchUnit)
+            .unit(unit_id)
+            .old_value(&old_unit)
+            .new_value(&unit),
+    );
+    // The limits may have been raised.
+    for exercise_index in 0..unit.exercise_count {
+        promote_waitlisted(
+            &tx,
+            &req,
+            config,
+            principal.student_id,
+            &unit,
+            exercise_index,
+        );
+    }
+    tx.commit().unwrap();
+
+    json(&unit, StatusCode::OK)
+}
+
+pub(crate) async fn delete_unit(
+    req: Request<Body>,
+    unit_id: u32,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Response<Body> {
+    const WHAT: &str = "unit deletion request";
+    let principal = match authenticate_teacher(&req, db, config, WHAT).await {
+        Ok(val) => val,
+        Err(res) => return res,
+    };
+
+    let db = db.lock().await;
+    let unit = match get_unit(&db, unit_id) {
+        Some(val) => val,
+        None => return empty(StatusCode::NOT_FOUND),
+    };
+    if let Some(used) = highest_used_exercise(&db, unit_id) {
+        return json(
+            &serde_json::json!({
+                "error": "exerciseInUse",
+                "exerciseIndex": used,
+            }),
+            StatusCode::CONFLICT,
+        );
+    }
+
+    let tx = db.unchecked_transaction().unwrap();
+    tx.execute("DELETE FROM exercise WHERE unit_id = ?", params![unit_id])
+        .unwrap();
+    tx.execute(
+        "DELETE FROM exercise_notices WHERE unit_id = ?",
+        params![unit_id],
+    )
+    .unwrap();
+    tx.execute("DELETE FROM units WHERE id = ?", params![unit_id])
+        .unwrap();
+    audit::record_for_req(
+        &tx,
+        &req,
+        config,
+        principal.student_id,
+        audit::Event::new(audit::Action::DeleteUnit)
+            .unit(unit_id)
+            .old_value(&unit),
+    );
+    tx.commit().unwrap();
+
+    empty(StatusCode::OK)
+}
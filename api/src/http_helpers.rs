@@ -1,15 +1,14 @@
 use std::convert::TryInto;
 
 use bytes::buf::BufMut;
-use hmac::{Hmac, Mac, NewMac};
 use http::{Request, StatusCode};
 use hyper::{body::HttpBody, Body, Response};
-use serde::Serialize;
-use sha2::Sha256;
+use rusqlite::Connection;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Mutex;
 
 use crate::config::Config;
-
-type HmacSha256 = Hmac<Sha256>;
+use crate::sessions::{self, Role};
 
 pub(crate) fn empty(status: StatusCode) -> Response<Body> {
     Response::builder()
@@ -32,7 +31,7 @@ pub(crate) fn get_bearer(req: &Request<Body>) -> Option<&str> {
     let auth_header = req.headers().get(http::header::AUTHORIZATION)?;
     let mut parts = auth_header.to_str().ok()?.split_ascii_whitespace();
     if let (Some(ty), Some(creds)) = (parts.next(), parts.next()) {
-        if ty.to_ascii_lowercase() == "bearer" {
+        if ty.eq_ignore_ascii_case("bearer") {
             return Some(creds);
         }
     }
@@ -43,26 +42,112 @@ pub(crate) fn get_bearer(req: &Request<Body>) -> Option<&str> {
 pub(crate) enum HttpAuthError {
     MissingBearer,
     MissingDot,
-    InvalidStudentId,
     InvalidSig,
+    InvalidClaims,
+    Expired,
+    Revoked,
+}
+
+/// The student on whose behalf a request is made.
+pub(crate) struct Principal {
+    pub student_id: u32,
+    pub session_id: i64,
+    pub role: Role,
+}
+
+impl Principal {
+    pub fn is_teacher(&self) -> bool {
+        self.role == Role::Teacher
+    }
+}
+
+/// Extracts the value of a parameter from the query string of a request.
+pub(crate) fn get_query_param(req: &Request<Body>, key: &str) -> Option<String> {
+    let query = req.uri().query()?;
+    serde_urlencoded::from_str::<Vec<(String, String)>>(query)
+        .ok()?
+        .into_iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v)
+}
+
+/// Checks the log in token of a request and returns who it was issued to.
+pub(crate) async fn get_logged_in_principal(
+    req: &Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Result<Principal, HttpAuthError> {
+    let bearer = get_bearer(req).ok_or(HttpAuthError::MissingBearer)?;
+    principal_from_token(bearer, db, config).await
+}
+
+/// Checks a log in token and returns who it was issued to.
+pub(crate) async fn principal_from_token(
+    token: &str,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Result<Principal, HttpAuthError> {
+    let claims = sessions::decode(token, config)?;
+    let db = db.lock().await;
+    sessions::check_not_revoked(&db, &claims)?;
+    Ok(Principal {
+        student_id: claims.student_id,
+        session_id: claims.session_id,
+        role: claims.role,
+    })
+}
+
+/// Checks a token created by `sessions::picture_token` for a picture and
+/// returns who it was issued to, as long as their session is still open.
+pub(crate) async fn principal_from_picture_token(
+    token: &str,
+    digest: &str,
+    db: &Mutex<Connection>,
+    config: &Config,
+) -> Result<Principal, HttpAuthError> {
+    let session_id = sessions::check_picture_token(token, digest, config)?;
+    let (student_id, role) = sessions::open_session(&*db.lock().await, session_id)?;
+    Ok(Principal {
+        student_id,
+        session_id,
+        role,
+    })
+}
+
+/// Checks that a request is authenticated, or builds the response refusing
+/// it. `what` describes the request in the logs.
+pub(crate) async fn authenticate(
+    req: &Request<Body>,
+    db: &Mutex<Connection>,
+    config: &Config,
+    what: &str,
+) -> Result<Principal, Response<Body>> {
+    get_logged_in_principal(req, db, config)
+        .await
+        .map_err(|err| {
+            warn_for_req(
+                req,
+                config,
+                &format!("{} with invalid authentication: {:?}", what, err),
+            );
+            empty(StatusCode::FORBIDDEN)
+        })
 }
 
-pub(crate) fn get_logged_in_user_id(
+/// Like `authenticate`, but also refuses requests that were not made by a
+/// teacher.
+pub(crate) async fn authenticate_teacher(
     req: &Request<Body>,
+    db: &Mutex<Connection>,
     config: &Config,
-) -> Result<u32, HttpAuthError> {
-    let bearer = get_bearer(&req).ok_or(HttpAuthError::MissingBearer)?;
-    let dot = bearer.find('.').ok_or(HttpAuthError::MissingDot)?;
-    let id_str = &bearer[..dot];
-    let id: u32 = id_str.parse().ok().ok_or(HttpAuthError::InvalidStudentId)?;
-    let sig = base64::decode_config(&bearer[(dot + 1)..], base64::URL_SAFE_NO_PAD)
-        .map_err(|_err| HttpAuthError::InvalidSig)?;
-    let mut hmac = HmacSha256::new_varkey(&config.secret).unwrap();
-    hmac.update(id_str.as_bytes());
-    if hmac.verify(&sig).is_err() {
-        return Err(HttpAuthError::InvalidSig);
+    what: &str,
+) -> Result<Principal, Response<Body>> {
+    let principal = authenticate(req, db, config, what).await?;
+    if !principal.is_teacher() {
+        warn_for_req(req, config, &format!("{} from a student", what));
+        return Err(empty(StatusCode::FORBIDDEN));
     }
-    Ok(id)
+    Ok(principal)
 }
 
 pub(crate) enum CollectBodyError {
@@ -96,13 +181,65 @@ pub(crate) async fn collect_body(
     Ok(r)
 }
 
-/// Warn about an issue that happened during the handling of a request.
-pub(crate) fn warn_for_req(req: &Request<Body>, config: &Config, msg: &str) {
-    let ip = config
+/// Reads and parses the JSON body of a request, or builds the response
+/// refusing it. `what` describes the request in the logs.
+pub(crate) async fn read_json_body<T: DeserializeOwned>(
+    req: &mut Request<Body>,
+    max_len: usize,
+    config: &Config,
+    what: &str,
+) -> Result<T, Response<Body>> {
+    let b = match collect_body(req.body_mut(), max_len).await {
+        Ok(val) => val,
+        Err(CollectBodyError::ReadError(err)) => {
+            warn_for_req(
+                req,
+                config,
+                &format!("failed to read body from {}: {:?}", what, err),
+            );
+            return Err(empty(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+        Err(CollectBodyError::TooLarge) => {
+            warn_for_req(req, config, &format!("{} body is too large", what));
+            return Err(empty(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+    };
+    serde_json::from_slice(&b).map_err(|err| {
+        warn_for_req(req, config, &format!("{} is invalid: {:?}", what, err));
+        empty(StatusCode::BAD_REQUEST)
+    })
+}
+
+/// Parses the query string of a request, or builds the response refusing it.
+/// `what` describes the request in the logs.
+#[allow(clippy::result_large_err)]
+pub(crate) fn parse_query<T: DeserializeOwned>(
+    req: &Request<Body>,
+    config: &Config,
+    what: &str,
+) -> Result<T, Response<Body>> {
+    serde_urlencoded::from_str(req.uri().query().unwrap_or("")).map_err(|err| {
+        warn_for_req(
+            req,
+            config,
+            &format!("{} has an invalid query string: {:?}", what, err),
+        );
+        empty(StatusCode::BAD_REQUEST)
+    })
+}
+
+/// Returns the IP address of the client, as given by the reverse proxy in the
+/// header configured with `REAL_IP_HEADER`.
+pub(crate) fn client_ip<'a>(req: &'a Request<Body>, config: &Config) -> Option<&'a str> {
+    config
         .real_ip_header
         .as_ref()
         .and_then(|h| req.headers().get(h))
         .and_then(|v| v.to_str().ok())
-        .unwrap_or("(unknown IP)");
+}
+
+/// Warn about an issue that happened during the handling of a request.
+pub(crate) fn warn_for_req(req: &Request<Body>, config: &Config, msg: &str) {
+    let ip = client_ip(req, config).unwrap_or("(unknown IP)");
     eprintln!("[{}] {}", ip, msg);
 }
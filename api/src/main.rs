@@ -1,11 +1,24 @@
 #[macro_use]
 mod http_helpers;
 
+mod assignment;
+mod audit;
+mod cleanup;
+mod cli;
 mod config;
+mod corrections;
+mod db;
+mod deadlines;
 mod handlers;
+mod metadata;
+mod passwords;
+mod roster;
+mod scanned_pdf;
+mod sessions;
 
 use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{future, panic::AssertUnwindSafe};
 
 use futures::FutureExt;
@@ -24,87 +37,195 @@ use crate::http_helpers::*;
 pub(crate) struct Globals {
     config: Config,
     db: Arc<Mutex<Connection>>,
+    /// Held while correction pictures are stored or collected, so that the
+    /// garbage collection does not delete the picture of a new correction.
+    pictures_lock: Mutex<()>,
 }
 
 impl Globals {
     pub fn new(config: Config, db: Arc<Mutex<Connection>>) -> Self {
-        Globals { config, db }
+        Globals {
+            config,
+            db,
+            pictures_lock: Mutex::new(()),
+        }
     }
 
     pub async fn handle(self: Arc<Globals>, req: Request<Body>) -> Response<Body> {
-        if req.method() == http::Method::POST && req.uri().path() == "/log-in" {
-            return handlers::log_in(req, &self.db, &self.config).await;
-        } else if req.method() == http::Method::GET && req.uri().path() == "/students/me" {
-            return handlers::me(req, &self.db, &self.config).await;
-        } else if req.method() == http::Method::GET && req.uri().path() == "/units" {
-            return handlers::units(req, &self.db, &self.config).await;
-        } else {
-            let mut segments = req.uri().path()[1..].split('/');
-            match segments.next() {
-                Some("units") => {
-                    if let Some(unit_id) = segments.next().and_then(|s| s.parse::<u32>().ok()) {
-                        if segments.next() == Some("exercises") {
-                            match segments.next() {
-                                Some(exercise_index_str) => {
-                                    if let Some(exercise_index) =
-                                        exercise_index_str.parse::<u32>().ok()
-                                    {
-                                        match segments.next() {
-                                            Some("corrections") => {
-                                                match segments.next() {
-                                                    Some(correction_digest)
-                                                        if req.method() == http::Method::DELETE
-                                                            && segments.next().is_none() =>
-                                                    {
-                                                        let correction_digest =
-                                                            correction_digest.to_owned();
-                                                        return handlers::delete_exercise_correction(req, unit_id, exercise_index, correction_digest, &self.db, &self.config).await;
-                                                    }
-                                                    None if req.method() == http::Method::POST => {
-                                                        return handlers::submit_exercise_correction(
-                                                req,
-                                                unit_id,
-                                                exercise_index,
-                                                &self.db,
-                                                &self.config,
-                                            )
-                                            .await;
-                                                    }
-                                                    _ => {}
-                                                }
-                                            }
-                                            None if req.method() == http::Method::PATCH => {
-                                                return handlers::patch_exercise(
-                                                    req,
-                                                    unit_id,
-                                                    exercise_index,
-                                                    &self.db,
-                                                    &self.config,
-                                                )
-                                                .await
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                }
-                                None if req.method() == http::Method::GET => {
-                                    return handlers::unit_exercises(
-                                        req,
-                                        unit_id,
-                                        &self.db,
-                                        &self.config,
-                                    )
-                                    .await
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                }
-                _ => {}
+        use http::Method;
+        use Segment::*;
+
+        let db = &self.db;
+        let pictures_lock = &self.pictures_lock;
+        let config = &self.config;
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+        let segments: Vec<Segment> = path[1..].split('/').map(Segment::parse).collect();
+
+        match (&method, segments.as_slice()) {
+            (&Method::POST, [Name("log-in")]) => handlers::log_in(req, db, config).await,
+            (&Method::POST, [Name("log-out")]) => handlers::log_out(req, db, config).await,
+            (&Method::GET, [Name("students"), Name("me")]) => handlers::me(req, db, config).await,
+            (&Method::PUT, [Name("students"), Name("me"), Name("password")]) => {
+                handlers::change_password(req, db, config).await
+            }
+            (&Method::GET, [Name("audit-log")]) => handlers::audit_log(req, db, config).await,
+            (&Method::GET, [Name("stats")]) => handlers::class_stats(req, db, config).await,
+            (&Method::GET, [Name("students")]) => handlers::list_students(req, db, config).await,
+            (&Method::POST, [Name("students")]) => handlers::create_student(req, db, config).await,
+            (&Method::POST, [Name("students"), Name("import")]) => {
+                handlers::import_students(req, db, config).await
+            }
+            (&Method::GET, [Name("students"), Id(student_id), Name("stats")]) => {
+                handlers::student_stats(req, *student_id, db, config).await
+            }
+            (&Method::GET, [Name("students"), Id(student_id), Name("feedback")]) => {
+                handlers::student_feedback(req, *student_id, db, config).await
+            }
+            (&Method::POST, [Name("students"), Id(student_id), Name("password-setup-code")]) => {
+                handlers::issue_password_setup_code(req, *student_id, db, config).await
+            }
+            (&Method::PATCH, [Name("students"), Id(student_id)]) => {
+                handlers::patch_student(req, *student_id, db, config).await
+            }
+            (&Method::DELETE, [Name("students"), Id(student_id)]) => {
+                handlers::delete_student(req, *student_id, db, config).await
+            }
+            (&Method::GET, [Name("units")]) => handlers::units(req, db, config).await,
+            (&Method::POST, [Name("units")]) => handlers::create_unit(req, db, config).await,
+            (&Method::PATCH, [Name("units"), Id(unit_id)]) => {
+                handlers::patch_unit(req, *unit_id, db, config).await
+            }
+            (&Method::DELETE, [Name("units"), Id(unit_id)]) => {
+                handlers::delete_unit(req, *unit_id, db, config).await
+            }
+            (&Method::POST, [Name("units"), Id(unit_id), Name("assignment")]) => {
+                handlers::assign_exercises(req, *unit_id, db, config).await
+            }
+            (&Method::GET, [Name("units"), Id(unit_id), Name("exercises")]) => {
+                handlers::unit_exercises(req, *unit_id, db, config).await
+            }
+            (
+                &Method::PATCH,
+                [Name("units"), Id(unit_id), Name("exercises"), Id(exercise_index)],
+            ) => handlers::patch_exercise(req, *unit_id, *exercise_index, db, config).await,
+            (
+                &Method::DELETE,
+                [Name("units"), Id(unit_id), Name("exercises"), Id(exercise_index), Name("notices")],
+            ) => handlers::dismiss_notices(req, *unit_id, *exercise_index, db, config).await,
+            (
+                &Method::PUT,
+                [Name("units"), Id(unit_id), Name("exercises"), Id(exercise_index), Name("students"), Id(student_id), Name("feedback")],
+            ) => {
+                handlers::set_presentation_feedback(
+                    req,
+                    *unit_id,
+                    *exercise_index,
+                    *student_id,
+                    db,
+                    config,
+                )
+                .await
+            }
+            (
+                &Method::POST,
+                [Name("units"), Id(unit_id), Name("exercises"), Id(exercise_index), Name("corrections")],
+            ) => {
+                handlers::submit_exercise_correction(
+                    req,
+                    *unit_id,
+                    *exercise_index,
+                    db,
+                    pictures_lock,
+                    config,
+                )
+                .await
+            }
+            (
+                &Method::DELETE,
+                [Name("units"), Id(unit_id), Name("exercises"), Id(exercise_index), Name("corrections"), Name(digest)],
+            ) => {
+                let digest = (*digest).to_owned();
+                handlers::delete_exercise_correction(
+                    req,
+                    *unit_id,
+                    *exercise_index,
+                    digest,
+                    db,
+                    config,
+                )
+                .await
+            }
+            (
+                &Method::POST,
+                [Name("units"), Id(unit_id), Name("exercises"), Id(exercise_index), Name("corrections"), Name(digest), Name("restore")],
+            ) => {
+                let digest = (*digest).to_owned();
+                handlers::restore_exercise_correction(
+                    req,
+                    *unit_id,
+                    *exercise_index,
+                    digest,
+                    db,
+                    config,
+                )
+                .await
             }
+            (&Method::GET, [Name("corrections"), Name("trash")]) => {
+                handlers::trashed_corrections(req, db, config).await
+            }
+            (&Method::GET, [Name("corrections"), Name(file_name)]) => {
+                let file_name = (*file_name).to_owned();
+                handlers::correction_picture(req, file_name, db, config).await
+            }
+            (&Method::POST, [Name("corrections"), Name("gc")]) => {
+                handlers::collect_correction_garbage(req, db, pictures_lock, config).await
+            }
+            _ => empty(StatusCode::NOT_FOUND),
+        }
+    }
+}
+
+/// A segment of the path of a request.
+enum Segment<'a> {
+    /// A numeric ID.
+    Id(u32),
+    Name(&'a str),
+}
+
+impl<'a> Segment<'a> {
+    fn parse(s: &'a str) -> Self {
+        match s.parse() {
+            Ok(id) => Segment::Id(id),
+            Err(_) => Segment::Name(s),
         }
-        empty(StatusCode::NOT_FOUND)
+    }
+}
+
+/// Purges the trash and deletes the correction pictures that are not used
+/// anymore periodically. The first collection, at startup, is a dry run that
+/// only logs what it would delete, so that a wrong configuration shows up in
+/// the logs before any picture is lost.
+async fn collect_correction_garbage_periodically(globals: Arc<Globals>, interval: Duration) {
+    let mut options = corrections::GcOptions {
+        dry_run: true,
+        force: false,
+    };
+    loop {
+        match corrections::collect_garbage(
+            &globals.db,
+            &globals.pictures_lock,
+            &globals.config.corrections_path,
+            globals.config.corrections_trash_retention,
+            options,
+        )
+        .await
+        {
+            Ok(report) => corrections::log_gc_report(&report),
+            Err(err) => eprintln!("failed to collect unused correction pictures: {:?}", err),
+        }
+        options.dry_run = false;
+        tokio::time::sleep(interval).await;
     }
 }
 
@@ -112,9 +233,26 @@ impl Globals {
 pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let config = Config::from_env_vars().expect("failed to read config");
     let addr = ([127, 0, 0, 1], config.port).into();
-    let db = Connection::open(&config.db_path)?;
+    let db = db::open(&config.db_path)?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return cli::run(&db, &config, &args);
+    }
+
+    // Held until the server stops, see `corrections::lock_directory`.
+    let _corrections_lock = match corrections::lock_directory(&config.corrections_path)? {
+        Some(val) => val,
+        None => return Err("another process uses the correction pictures directory".into()),
+    };
 
     let globals = Arc::new(Globals::new(config, Arc::new(Mutex::new(db))));
+    if let Some(interval) = globals.config.corrections_gc_interval {
+        tokio::spawn(collect_correction_garbage_periodically(
+            globals.clone(),
+            interval,
+        ));
+    }
 
     // For every connection, we must make a `Service` to handle all
     // incoming HTTP requests on said connection.
@@ -126,12 +264,7 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let svc = service_fn(move |req| {
             let globals = globals.clone();
             async move {
-                let ip = globals
-                    .config
-                    .real_ip_header
-                    .as_ref()
-                    .and_then(|h| req.headers().get(h))
-                    .and_then(|v| v.to_str().ok())
+                let ip = client_ip(&req, &globals.config)
                     .unwrap_or("(unknown IP)")
                     .to_owned();
                 let err = {
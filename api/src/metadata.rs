@@ -0,0 +1,347 @@
+//! Handling of the metadata embedded in uploaded pictures.
+//!
+//! Phones store pictures the way the sensor sees them and record how they
+//! must be rotated in the EXIF orientation tag, which the decoder ignores.
+//! The same EXIF data may also contain the location where the picture was
+//! taken, so nothing but the pixels must be kept in what is stored.
+
+use std::convert::TryInto;
+
+use image::DynamicImage;
+
+/// The chunks that are kept in PNG files: the ones needed to display the
+/// picture, and nothing that could describe where it comes from.
+const PNG_KEPT_CHUNKS: [&[u8; 4]; 5] = [b"IHDR", b"PLTE", b"tRNS", b"IDAT", b"IEND"];
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+
+/// Iterates over the segments of a JPEG file before the image data, as their
+/// marker and their range in the file including the marker. Returns `None`
+/// for the position of the image data if the file is invalid.
+fn jpeg_segments(data: &[u8]) -> (Vec<(u8, usize, usize)>, Option<usize>) {
+    let mut segments = Vec::new();
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return (segments, None);
+    }
+    let mut pos = 2;
+    loop {
+        // Markers may be preceded by any count of fill bytes.
+        let start = pos;
+        while data.get(pos) == Some(&0xff) {
+            pos += 1;
+        }
+        let marker = match data.get(pos) {
+            Some(&val) if pos > start => val,
+            _ => return (segments, None),
+        };
+        pos += 1;
+        // Start of scan, where the image data begins.
+        if marker == 0xda {
+            return (segments, Some(start));
+        }
+        if (0xd0..=0xd7).contains(&marker) || marker == 0x01 {
+            segments.push((marker, start, pos));
+            continue;
+        }
+        let len = match data.get(pos..(pos + 2)) {
+            Some(val) => u16::from_be_bytes([val[0], val[1]]) as usize,
+            None => return (segments, None),
+        };
+        if len < 2 || pos + len > data.len() {
+            return (segments, None);
+        }
+        pos += len;
+        segments.push((marker, start, pos));
+    }
+}
+
+/// Reads the EXIF orientation of a JPEG file, from 1 to 8.
+pub(crate) fn jpeg_orientation(data: &[u8]) -> Option<u16> {
+    let (segments, _) = jpeg_segments(data);
+    segments
+        .iter()
+        .filter(|(marker, _, _)| *marker == 0xe1)
+        .find_map(|&(_, start, end)| {
+            // The marker and the length come before the content.
+            let content = data.get((start + 4)..end)?;
+            let tiff = content.strip_prefix(b"Exif\0\0")?;
+            tiff_orientation(tiff)
+        })
+}
+
+/// Reads the width and height of a JPEG file from its frame header, without
+/// decoding it.
+pub(crate) fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let (segments, _) = jpeg_segments(data);
+    segments
+        .iter()
+        // The start of frame markers, except for the ones in their range that
+        // mean something else.
+        .filter(|(marker, _, _)| {
+            (0xc0..=0xcf).contains(marker) && ![0xc4, 0xc8, 0xcc].contains(marker)
+        })
+        .find_map(|&(_, start, end)| {
+            let content = data.get((start + 4)..end)?;
+            let height = u16::from_be_bytes([*content.get(1)?, *content.get(2)?]);
+            let width = u16::from_be_bytes([*content.get(3)?, *content.get(4)?]);
+            Some((width as u32, height as u32))
+        })
+}
+
+/// Reads the orientation tag in the first IFD of EXIF data.
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(0..2)? {
+        b"II" => false,
+        b"MM" => true,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| -> Option<u16> {
+        let b: [u8; 2] = tiff.get(pos..(pos + 2))?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    };
+    let u32_at = |pos: usize| -> Option<u32> {
+        let b: [u8; 4] = tiff.get(pos..(pos + 4))?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    };
+    if u16_at(2)? != 42 {
+        return None;
+    }
+    let ifd = u32_at(4)? as usize;
+    let count = u16_at(ifd)? as usize;
+    (0..count)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| u16_at(entry) == Some(EXIF_ORIENTATION_TAG))
+        .and_then(|entry| u16_at(entry + 8))
+        .filter(|o| (1..=8).contains(o))
+}
+
+/// Rotates and flips a picture so that it is displayed upright, according to
+/// its EXIF orientation.
+pub(crate) fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Removes the chunks of a PNG file that are not needed to display it, such
+/// as text and EXIF chunks.
+pub(crate) fn strip_png(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(PNG_SIGNATURE);
+    let mut pos = PNG_SIGNATURE.len();
+    // A chunk is made of its length, its type, its data and a CRC.
+    while let Some(header) = data.get(pos..(pos + 8)) {
+        let len = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let end = pos + 12 + len;
+        let chunk = match data.get(pos..end) {
+            Some(val) => val,
+            None => break,
+        };
+        if PNG_KEPT_CHUNKS.iter().any(|&t| t == &header[4..8]) {
+            out.extend_from_slice(chunk);
+        }
+        pos = end;
+    }
+    out
+}
+
+/// Removes the application specific segments and the comments of a JPEG
+/// file, except for the JFIF header.
+pub(crate) fn strip_jpeg(data: &[u8]) -> Vec<u8> {
+    let (segments, scan_start) = jpeg_segments(data);
+    let scan_start = match scan_start {
+        Some(val) => val,
+        // There is nothing to keep in a file that cannot be read.
+        None => return Vec::new(),
+    };
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[0..2]);
+    for (marker, start, end) in segments {
+        let is_metadata = (0xe1..=0xef).contains(&marker) || marker == 0xfe;
+        if !is_metadata {
+            out.extend_from_slice(&data[start..end]);
+        }
+    }
+    out.extend_from_slice(&data[scan_start..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{GenericImageView, ImageOutputFormat, Rgb, RgbImage};
+
+    /// A picture whose orientation can be told from its corners: red at the
+    /// top left, green at the top right and blue at the bottom left.
+    fn upright() -> RgbImage {
+        RgbImage::from_fn(32, 16, |x, y| match (x < 16, y < 8) {
+            (true, true) => Rgb([255, 0, 0]),
+            (false, true) => Rgb([0, 255, 0]),
+            (true, false) => Rgb([0, 0, 255]),
+            (false, false) => Rgb([255, 255, 255]),
+        })
+    }
+
+    /// The inverse of the transformation for an orientation, which is how the
+    /// camera stores the picture.
+    fn as_stored(image: RgbImage, orientation: u16) -> DynamicImage {
+        let image = DynamicImage::ImageRgb8(image);
+        match orientation {
+            5 => image.fliph().rotate270(),
+            6 => image.rotate270(),
+            7 => image.fliph().rotate90(),
+            8 => image.rotate90(),
+            o => apply_orientation(image, o),
+        }
+    }
+
+    /// Builds an EXIF segment with an orientation and a GPS IFD pointer.
+    fn exif_segment(orientation: u16, big_endian: bool) -> Vec<u8> {
+        let u16_bytes = |v: u16| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let u32_bytes = |v: u32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let mut tiff = Vec::new();
+        tiff.extend_from_slice(if big_endian { b"MM" } else { b"II" });
+        tiff.extend_from_slice(&u16_bytes(42));
+        tiff.extend_from_slice(&u32_bytes(8));
+        tiff.extend_from_slice(&u16_bytes(2));
+        // The orientation, as a short.
+        tiff.extend_from_slice(&u16_bytes(EXIF_ORIENTATION_TAG));
+        tiff.extend_from_slice(&u16_bytes(3));
+        tiff.extend_from_slice(&u32_bytes(1));
+        tiff.extend_from_slice(&u16_bytes(orientation));
+        tiff.extend_from_slice(&[0, 0]);
+        // The GPS IFD, which points nowhere but is enough to be recognized.
+        tiff.extend_from_slice(&u16_bytes(0x8825));
+        tiff.extend_from_slice(&u16_bytes(4));
+        tiff.extend_from_slice(&u32_bytes(1));
+        tiff.extend_from_slice(&u32_bytes(0));
+        tiff.extend_from_slice(&u32_bytes(0));
+
+        let mut segment = vec![0xff, 0xe1];
+        segment.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        segment.extend_from_slice(b"Exif\0\0");
+        segment.extend_from_slice(&tiff);
+        segment
+    }
+
+    /// Encodes a sample JPEG, like a phone would, with its EXIF segment right
+    /// after the start of image marker.
+    fn sample_jpeg(orientation: u16, big_endian: bool) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        as_stored(upright(), orientation)
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(95))
+            .unwrap();
+        let mut out = jpeg[0..2].to_vec();
+        out.extend_from_slice(&exif_segment(orientation, big_endian));
+        out.extend_from_slice(b"\xff\xfe\x00\x0bGPS notes");
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    fn assert_upright(image: &DynamicImage) {
+        assert_eq!(image.dimensions(), (32, 16));
+        let close = |p: image::Rgba<u8>, expected: [u8; 3]| {
+            p.0.iter()
+                .zip(expected.iter())
+                .all(|(&a, &b)| (a as i16 - b as i16).abs() < 40)
+        };
+        assert!(close(image.get_pixel(4, 4), [255, 0, 0]));
+        assert!(close(image.get_pixel(27, 4), [0, 255, 0]));
+        assert!(close(image.get_pixel(4, 11), [0, 0, 255]));
+        assert!(close(image.get_pixel(27, 11), [255, 255, 255]));
+    }
+
+    #[test]
+    fn applies_every_orientation() {
+        for &big_endian in &[false, true] {
+            for orientation in 1..=8 {
+                let jpeg = sample_jpeg(orientation, big_endian);
+                assert_eq!(jpeg_orientation(&jpeg), Some(orientation));
+                let image = image::load_from_memory(&jpeg).unwrap();
+                assert_upright(&apply_orientation(image, orientation));
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_missing_or_invalid_orientation() {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(upright())
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(95))
+            .unwrap();
+        assert_eq!(jpeg_orientation(&jpeg), None);
+        assert_eq!(jpeg_orientation(&sample_jpeg(9, false)), None);
+        assert_eq!(jpeg_orientation(b"not a picture"), None);
+    }
+
+    #[test]
+    fn strips_jpeg_metadata() {
+        let jpeg = sample_jpeg(6, false);
+        let stripped = strip_jpeg(&jpeg);
+        assert!(!stripped.windows(4).any(|w| w == b"Exif"));
+        assert!(!stripped.windows(3).any(|w| w == b"GPS"));
+        assert_eq!(jpeg_orientation(&stripped), None);
+        let image = image::load_from_memory(&stripped).unwrap();
+        assert_eq!(image.dimensions(), (16, 32));
+    }
+
+    #[test]
+    fn strips_png_metadata() {
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(upright())
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        // Add a text chunk after the header, whose CRC is not checked.
+        let mut with_text = png[0..33].to_vec();
+        with_text.extend_from_slice(&8u32.to_be_bytes());
+        with_text.extend_from_slice(b"tEXtGPS\x0048.8");
+        with_text.extend_from_slice(&[0; 4]);
+        with_text.extend_from_slice(&png[33..]);
+
+        let stripped = strip_png(&with_text);
+        assert_eq!(stripped, png);
+        assert_upright(&image::load_from_memory(&stripped).unwrap());
+    }
+
+    #[test]
+    fn uploaded_photos_end_up_upright_and_clean() {
+        let jpeg = sample_jpeg(8, true);
+        let image = image::load_from_memory(&jpeg).unwrap();
+        let image = apply_orientation(image, jpeg_orientation(&jpeg).unwrap());
+        let mut png = Vec::new();
+        image.write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        let png = strip_png(&png);
+        assert!(!png.windows(4).any(|w| w == b"Exif" || w == b"eXIf"));
+        assert_upright(&image::load_from_memory(&png).unwrap());
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::OnceLock;
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand_core::{OsRng, RngCore};
+use rusqlite::{params, Connection};
+use subtle::ConstantTimeEq;
+
+use crate::config::Config;
+
+/// The minimum length, in bytes, of a password chosen by a student.
+pub(crate) const MIN_PASSWORD_LEN: usize = 8;
+
+/// The maximum length, in bytes, of a password chosen by a student. Hashing
+/// is slow so we do not want to hash arbitrarily long inputs.
+pub(crate) const MAX_PASSWORD_LEN: usize = 256;
+
+/// Hashes a password with Argon2 and a random salt. The result is a PHC
+/// string that contains the parameters and the salt.
+///
+/// Hashing takes a while, so it is done on a blocking thread to keep serving
+/// the other requests meanwhile.
+pub(crate) async fn hash(password: &str) -> String {
+    let password = password.to_owned();
+    tokio::task::spawn_blocking(move || hash_now(&password))
+        .await
+        .expect("failed to hash password")
+}
+
+fn hash_now(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("failed to hash password")
+        .to_string()
+}
+
+/// The hash that passwords are checked against when there is no actual hash
+/// to check them against, so that it takes as long as when there is one.
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_now("not the password of anyone"))
+}
+
+/// Checks a password against a hash on a blocking thread, see `hash`.
+async fn verify_hash(password: &str, password_hash: &str) -> bool {
+    let password = password.to_owned();
+    let password_hash = password_hash.to_owned();
+    tokio::task::spawn_blocking(move || match PasswordHash::new(&password_hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(err) => {
+            eprintln!("invalid password hash in database: {:?}", err);
+            false
+        }
+    })
+    .await
+    .expect("failed to verify password")
+}
+
+/// Checks a password against the hash of the password of a student, or
+/// against the shared password if the student did not choose one yet.
+///
+/// Both comparisons are done in constant time, and both take as long as
+/// hashing the password, so that the response time does not tell whether the
+/// student chose a password.
+pub(crate) async fn verify(password: &str, password_hash: Option<&str>, config: &Config) -> bool {
+    let matches_hash = verify_hash(password, password_hash.unwrap_or(dummy_hash())).await;
+    match password_hash {
+        Some(_) => matches_hash,
+        None => password.as_bytes().ct_eq(config.password.as_bytes()).into(),
+    }
+}
+
+/// Takes as long as `verify` without checking anything, for log in attempts
+/// with unknown usernames, so that the response time does not tell which
+/// usernames exist.
+pub(crate) async fn verify_dummy(password: &str) {
+    verify_hash(password, dummy_hash()).await;
+}
+
+/// How long a password setup code can be used after it was issued.
+const SETUP_CODE_LIFETIME_DAYS: u32 = 7;
+
+/// The characters of a setup code, without the ones that look alike. There
+/// are 32 of them so that a random byte maps to one without bias.
+const SETUP_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// A one-time code that lets a student choose their password, see the
+/// `password_setup_code_hash` column.
+pub(crate) struct SetupCode {
+    /// The code to give to the student, such as `ABCD-EFGH-JKLM`.
+    pub code: String,
+    hash: String,
+}
+
+/// Generates a random setup code and hashes it. This is slow, see `hash`.
+pub(crate) fn generate_setup_code() -> SetupCode {
+    let mut bytes = [0u8; 12];
+    OsRng.fill_bytes(&mut bytes);
+    let chars: Vec<u8> = bytes
+        .iter()
+        .map(|b| SETUP_CODE_ALPHABET[usize::from(b % 32)])
+        .collect();
+    let code = chars
+        .chunks(4)
+        .map(|c| std::str::from_utf8(c).unwrap())
+        .collect::<Vec<_>>()
+        .join("-");
+    SetupCode {
+        hash: hash_now(&normalize_setup_code(&code)),
+        code,
+    }
+}
+
+/// Ignores the case and the separators of a setup code typed by a student.
+pub(crate) fn normalize_setup_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Saves the setup code of a student, replacing the previous one, and returns
+/// when it expires, in RFC 3339 format.
+pub(crate) fn save_setup_code(db: &Connection, student_id: u32, setup_code: &SetupCode) -> String {
+    db.execute(
+        "UPDATE students SET password_setup_code_hash = ?, password_setup_code_expires_at = datetime('now', ?) WHERE id = ?",
+        params![
+            setup_code.hash,
+            format!("+{} days", SETUP_CODE_LIFETIME_DAYS),
+            student_id
+        ],
+    )
+    .unwrap();
+    db.query_row(
+        "SELECT strftime('%Y-%m-%dT%H:%M:%SZ', password_setup_code_expires_at) FROM students WHERE id = ?",
+        params![student_id],
+        |r| r.get(0),
+    )
+    .unwrap()
+}
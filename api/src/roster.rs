@@ -0,0 +1,304 @@
+use std::collections::HashSet;
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+const MAX_USERNAME_LEN: usize = 64;
+const MAX_FULL_NAME_LEN: usize = 200;
+const MAX_EMAIL_LEN: usize = 254;
+
+/// Usernames are typed by the students to log in, so they are kept simple.
+pub(crate) fn is_valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= MAX_USERNAME_LEN
+        && username
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'.')
+}
+
+pub(crate) fn is_valid_full_name(full_name: &str) -> bool {
+    !full_name.trim().is_empty() && full_name.len() <= MAX_FULL_NAME_LEN
+}
+
+/// Only catches obvious typos: the address is not used to send anything yet.
+pub(crate) fn is_valid_email(email: &str) -> bool {
+    let mut parts = email.split('@');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(local), Some(domain), None) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && email.len() <= MAX_EMAIL_LEN
+                && !email.chars().any(char::is_whitespace)
+        }
+        _ => false,
+    }
+}
+
+/// A problem with a line of an imported roster.
+#[derive(Serialize, Debug)]
+pub(crate) struct ImportError {
+    /// The line number in the CSV file, starting at 1 for the header.
+    pub line: u64,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ImportSummary {
+    pub created: u32,
+    pub updated: u32,
+}
+
+#[derive(Debug)]
+struct ImportedStudent {
+    username: String,
+    full_name: String,
+    in_group_even: bool,
+    email: Option<String>,
+}
+
+fn parse_group(s: &str) -> Option<bool> {
+    match s.trim().to_lowercase().as_str() {
+        "even" | "pair" => Some(true),
+        "odd" | "impair" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses a roster in the CSV format. The first line is a header that names
+/// the columns: `username`, `full_name`, `group` (`even` or `odd`) and
+/// optionally `email`, in any order.
+fn parse_csv(data: &[u8]) -> Result<Vec<ImportedStudent>, Vec<ImportError>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(data);
+
+    let headers = match reader.headers() {
+        Ok(val) => val.clone(),
+        Err(err) => {
+            return Err(vec![ImportError {
+                line: 1,
+                message: format!("invalid header: {}", err),
+            }])
+        }
+    };
+    let column = |name: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let (username_col, full_name_col, group_col) =
+        match (column("username"), column("full_name"), column("group")) {
+            (Some(u), Some(f), Some(g)) => (u, f, g),
+            _ => {
+                return Err(vec![ImportError {
+                    line: 1,
+                    message: "the header must contain username, full_name and group".to_owned(),
+                }])
+            }
+        };
+    let email_col = column("email");
+
+    let mut students = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(val) => val,
+            Err(err) => {
+                errors.push(ImportError {
+                    line: err.position().map_or(0, |p| p.line()),
+                    message: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |p| p.line());
+        let mut error = |message: String| errors.push(ImportError { line, message });
+
+        let username = record.get(username_col).unwrap_or("");
+        let full_name = record.get(full_name_col).unwrap_or("");
+        let group = record.get(group_col).unwrap_or("");
+        let email = email_col
+            .and_then(|c| record.get(c))
+            .filter(|e| !e.is_empty());
+
+        if !is_valid_username(username) {
+            error(format!("invalid username {:?}", username));
+            continue;
+        }
+        if !seen.insert(username.to_owned()) {
+            error(format!("duplicate username {:?}", username));
+            continue;
+        }
+        if !is_valid_full_name(full_name) {
+            error(format!("invalid full name {:?}", full_name));
+            continue;
+        }
+        let in_group_even = match parse_group(group) {
+            Some(val) => val,
+            None => {
+                error(format!("invalid group {:?}, expected even or odd", group));
+                continue;
+            }
+        };
+        if let Some(email) = email {
+            if !is_valid_email(email) {
+                error(format!("invalid email {:?}", email));
+                continue;
+            }
+        }
+
+        students.push(ImportedStudent {
+            username: username.to_owned(),
+            full_name: full_name.to_owned(),
+            in_group_even,
+            email: email.map(str::to_owned),
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(students)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Imports a roster in the CSV format, creating the students that do not
+/// exist and updating the others, matched by username.
+///
+/// Nothing is written unless every line is valid, so a corrected file can
+/// simply be imported again.
+pub(crate) fn import_csv(db: &Connection, data: &[u8]) -> Result<ImportSummary, Vec<ImportError>> {
+    let students = parse_csv(data)?;
+
+    let mut summary = ImportSummary {
+        created: 0,
+        updated: 0,
+    };
+    let tx = db.unchecked_transaction().unwrap();
+    for s in students {
+        let exists: bool = tx
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM students WHERE username = ?)",
+                params![s.username],
+                |r| r.get(0),
+            )
+            .unwrap();
+        // A student listed in the roster is part of the class, even if they
+        // were deactivated before. The email is kept when it is not given.
+        tx.execute(
+            "INSERT INTO students (username, full_name, in_group_even, email) VALUES (?, ?, ?, ?) ON CONFLICT (username) DO UPDATE SET full_name = excluded.full_name, in_group_even = excluded.in_group_even, email = COALESCE(excluded.email, email), active = TRUE",
+            params![s.username, s.full_name, s.in_group_even, s.email],
+        )
+        .unwrap();
+        if exists {
+            summary.updated += 1;
+        } else {
+            summary.created += 1;
+        }
+    }
+    tx.commit().unwrap();
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rusqlite::NO_PARAMS;
+
+    use crate::db;
+
+    fn usernames(students: &[ImportedStudent]) -> Vec<&str> {
+        students.iter().map(|s| s.username.as_str()).collect()
+    }
+
+    #[test]
+    fn reads_columns_from_the_header() {
+        let students = parse_csv(
+            b"Group,email,username,full_name\n\
+              even,,alice,Alice A\n\
+              odd,bob@example.com,bob,Bob B\n",
+        )
+        .unwrap();
+        assert_eq!(usernames(&students), ["alice", "bob"]);
+        assert!(students[0].in_group_even);
+        assert_eq!(students[0].email, None);
+        assert_eq!(students[1].email.as_deref(), Some("bob@example.com"));
+
+        let errors = parse_csv(b"username,full_name\nalice,Alice A\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 1);
+    }
+
+    #[test]
+    fn reads_quoted_fields() {
+        let students = parse_csv(
+            b"username,full_name,group\n\
+              alice,\"Martin, Alice \"\"Ali\"\"\",even\n",
+        )
+        .unwrap();
+        assert_eq!(students[0].full_name, "Martin, Alice \"Ali\"");
+    }
+
+    #[test]
+    fn reads_groups_in_english_and_french() {
+        let students = parse_csv(
+            b"username,full_name,group\n\
+              a,A,even\n\
+              b,B,Pair\n\
+              c,C,odd\n\
+              d,D,IMPAIR\n",
+        )
+        .unwrap();
+        let groups: Vec<bool> = students.iter().map(|s| s.in_group_even).collect();
+        assert_eq!(groups, [true, true, false, false]);
+    }
+
+    #[test]
+    fn reports_errors_by_line() {
+        let errors = parse_csv(
+            b"username,full_name,group\n\
+              alice,Alice A,even\n\
+              bob,Bob B,third\n\
+              alice,Alice A,odd\n",
+        )
+        .unwrap_err();
+        let lines: Vec<u64> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [3, 4]);
+        assert!(errors[0].message.contains("invalid group"));
+        assert!(errors[1].message.contains("duplicate username"));
+    }
+
+    #[test]
+    fn updates_existing_students() {
+        let mut db = Connection::open_in_memory().unwrap();
+        db::migrate(&mut db).unwrap();
+        db.execute(
+            "INSERT INTO students (username, full_name, in_group_even, email, active) VALUES ('alice', 'Old Name', FALSE, 'alice@example.com', FALSE)",
+            NO_PARAMS,
+        )
+        .unwrap();
+
+        let summary = import_csv(
+            &db,
+            b"username,full_name,group\nalice,Alice A,even\nbob,Bob B,odd\n",
+        )
+        .unwrap();
+        assert_eq!((summary.created, summary.updated), (1, 1));
+        let alice: (String, bool, Option<String>, bool) = db
+            .query_row(
+                "SELECT full_name, in_group_even, email, active FROM students WHERE username = 'alice'",
+                NO_PARAMS,
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            alice,
+            (
+                "Alice A".to_owned(),
+                true,
+                Some("alice@example.com".to_owned()),
+                true
+            )
+        );
+    }
+}
@@ -0,0 +1,1393 @@
+//! Scanned-image-only extraction of the pages of PDF documents.
+//!
+//! This is not a PDF renderer: scanners and scanning apps store every page as
+//! a single picture, so the largest picture of each page is decoded instead
+//! of rendering the content stream. Anything drawn over that picture, such as
+//! annotations, is lost, and documents with vector content, such as text typed
+//! on a computer, are refused with `PdfErrorKind::NotScanned`, so that the
+//! user can be asked for photos instead.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+
+use image::{DynamicImage, GrayImage, ImageFormat, RgbImage};
+
+use crate::metadata;
+
+/// The maximum count of pages in a document.
+pub(crate) const MAX_PAGES: usize = 20;
+
+/// The maximum width or height of a page, the same as for the other pictures.
+const MAX_DIMENSION: u32 = 10_000;
+
+/// The maximum total count of pixels of the pages of a document, which is
+/// checked before decoding each page. A single page may use all of it.
+const MAX_DOCUMENT_PIXELS: u64 = MAX_DIMENSION as u64 * MAX_DIMENSION as u64;
+
+/// Nested dictionaries and page trees deeper than this are refused, so that a
+/// malicious document cannot make the parser recurse forever.
+const MAX_DEPTH: u32 = 32;
+
+/// The maximum count of objects in an object stream.
+const MAX_OBJECTS_PER_STREAM: usize = 10_000;
+
+/// The maximum size of the decoded object streams of a document, which only
+/// contain dictionaries and are much smaller than pictures.
+const MAX_OBJECT_STREAMS_SIZE: usize = 16 * 1024 * 1024;
+
+/// Why the pages of a document could not be extracted, for the user to know
+/// what to send instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PdfErrorKind {
+    /// The document is not a valid PDF document.
+    Invalid,
+    /// A page is not a scanned picture, such as a page of typed text.
+    NotScanned,
+    /// A picture is stored in a way that is not supported, such as with a
+    /// compression made for black and white scans.
+    Unsupported,
+    /// The document has too many pages, or pages that are too large.
+    TooLarge,
+}
+
+#[derive(Debug)]
+pub(crate) struct PdfError {
+    pub(crate) kind: PdfErrorKind,
+    /// The page where the error occurred, from 1, if it is about a page.
+    pub(crate) page: Option<usize>,
+    message: String,
+}
+
+impl fmt::Display for PdfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.page {
+            Some(page) => write!(f, "page {}: {}", page, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, PdfError>;
+
+fn error<T>(kind: PdfErrorKind, msg: impl Into<String>) -> Result<T> {
+    Err(PdfError {
+        kind,
+        page: None,
+        message: msg.into(),
+    })
+}
+
+fn err<T>(msg: impl Into<String>) -> Result<T> {
+    error(PdfErrorKind::Invalid, msg)
+}
+
+/// Checks whether some data looks like a PDF document.
+pub(crate) fn is_pdf(data: &[u8]) -> bool {
+    data.starts_with(b"%PDF-")
+}
+
+#[derive(Clone, Debug)]
+enum Object {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Real(f64),
+    Name(Vec<u8>),
+    Str(Vec<u8>),
+    Array(Vec<Object>),
+    Dict(Dict),
+    Stream(Dict, Vec<u8>),
+    Ref(u32),
+}
+
+type Dict = HashMap<Vec<u8>, Object>;
+
+impl Object {
+    fn as_int(&self) -> Option<i64> {
+        match self {
+            Object::Int(val) => Some(*val),
+            Object::Real(val) => Some(*val as i64),
+            _ => None,
+        }
+    }
+
+    fn as_name(&self) -> Option<&[u8]> {
+        match self {
+            Object::Name(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    fn as_dict(&self) -> Option<&Dict> {
+        match self {
+            Object::Dict(val) | Object::Stream(val, _) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b'\0' | b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(
+        b,
+        b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%'
+    )
+}
+
+fn is_regular(b: u8) -> bool {
+    !is_whitespace(b) && !is_delimiter(b)
+}
+
+/// A parser for the objects in the body of a document.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Parser { data, pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            if is_whitespace(b) {
+                self.pos += 1;
+            } else if b == b'%' {
+                while !matches!(self.peek(), None | Some(b'\r') | Some(b'\n')) {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Reads a keyword or a number.
+    fn regular_token(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(is_regular) {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn keyword(&mut self, keyword: &[u8]) -> bool {
+        self.skip_whitespace();
+        let start = self.pos;
+        if self.regular_token() == keyword {
+            true
+        } else {
+            self.pos = start;
+            false
+        }
+    }
+
+    fn object(&mut self, depth: u32) -> Result<Object> {
+        if depth > MAX_DEPTH {
+            return err("objects are nested too deeply");
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            None => err("unexpected end of document"),
+            Some(b'/') => {
+                self.pos += 1;
+                Ok(Object::Name(self.name()))
+            }
+            Some(b'(') => {
+                self.pos += 1;
+                self.literal_string().map(Object::Str)
+            }
+            Some(b'<') if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                self.dict(depth).map(Object::Dict)
+            }
+            Some(b'<') => {
+                self.pos += 1;
+                self.hex_string().map(Object::Str)
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some(b']') {
+                        self.pos += 1;
+                        return Ok(Object::Array(items));
+                    }
+                    items.push(self.object(depth + 1)?);
+                }
+            }
+            Some(_) => {
+                let token = self.regular_token();
+                match token {
+                    b"true" => Ok(Object::Bool(true)),
+                    b"false" => Ok(Object::Bool(false)),
+                    b"null" => Ok(Object::Null),
+                    _ => self.number(token),
+                }
+            }
+        }
+    }
+
+    /// Parses a number, or a reference if it is followed by a generation
+    /// number and `R`.
+    fn number(&mut self, token: &[u8]) -> Result<Object> {
+        let s = std::str::from_utf8(token).unwrap_or("");
+        if let Ok(val) = s.parse::<i64>() {
+            let after = self.pos;
+            self.skip_whitespace();
+            let gen = self.regular_token();
+            if !gen.is_empty() && gen.iter().all(u8::is_ascii_digit) && self.keyword(b"R") {
+                return match u32::try_from(val) {
+                    Ok(id) => Ok(Object::Ref(id)),
+                    Err(_) => err("invalid object number"),
+                };
+            }
+            self.pos = after;
+            return Ok(Object::Int(val));
+        }
+        match s.parse::<f64>() {
+            Ok(val) => Ok(Object::Real(val)),
+            Err(_) => err(format!(
+                "unexpected token {:?}",
+                String::from_utf8_lossy(token)
+            )),
+        }
+    }
+
+    fn name(&mut self) -> Vec<u8> {
+        let mut name = Vec::new();
+        while let Some(b) = self.peek().filter(|&b| is_regular(b)) {
+            self.pos += 1;
+            if b == b'#' {
+                let hex = self.data.get(self.pos..(self.pos + 2));
+                let decoded = hex
+                    .and_then(|h| std::str::from_utf8(h).ok())
+                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                if let Some(val) = decoded {
+                    name.push(val);
+                    self.pos += 2;
+                    continue;
+                }
+            }
+            name.push(b);
+        }
+        name
+    }
+
+    fn literal_string(&mut self) -> Result<Vec<u8>> {
+        let mut s = Vec::new();
+        let mut nesting = 0;
+        loop {
+            let b = match self.peek() {
+                Some(val) => val,
+                None => return err("unterminated string"),
+            };
+            self.pos += 1;
+            match b {
+                b'(' => nesting += 1,
+                b')' if nesting == 0 => return Ok(s),
+                b')' => nesting -= 1,
+                b'\\' => {
+                    // Only the content of the escapes that matter for
+                    // finding the end of the string is kept.
+                    if let Some(e) = self.peek() {
+                        self.pos += 1;
+                        s.push(e);
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            s.push(b);
+        }
+    }
+
+    fn hex_string(&mut self) -> Result<Vec<u8>> {
+        let mut digits = Vec::new();
+        loop {
+            match self.peek() {
+                None => return err("unterminated hexadecimal string"),
+                Some(b'>') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b) => {
+                    self.pos += 1;
+                    if let Some(d) = (b as char).to_digit(16) {
+                        digits.push(d as u8);
+                    }
+                }
+            }
+        }
+        if digits.len() % 2 == 1 {
+            digits.push(0);
+        }
+        Ok(digits.chunks(2).map(|c| c[0] << 4 | c[1]).collect())
+    }
+
+    fn dict(&mut self, depth: u32) -> Result<Dict> {
+        let mut dict = Dict::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b'>') if self.data.get(self.pos + 1) == Some(&b'>') => {
+                    self.pos += 2;
+                    return Ok(dict);
+                }
+                Some(b'/') => {
+                    self.pos += 1;
+                    let key = self.name();
+                    let value = self.object(depth + 1)?;
+                    dict.insert(key, value);
+                }
+                _ => return err("invalid dictionary key"),
+            }
+        }
+    }
+
+    /// Parses an indirect object whose header was already read, along with
+    /// its stream if it has one.
+    fn indirect_object(&mut self) -> Result<Object> {
+        let object = self.object(0)?;
+        let dict = match object {
+            Object::Dict(val) if self.keyword(b"stream") => val,
+            _ => return Ok(object),
+        };
+        match self.peek() {
+            Some(b'\r') if self.data.get(self.pos + 1) == Some(&b'\n') => self.pos += 2,
+            Some(b'\r') | Some(b'\n') => self.pos += 1,
+            _ => {}
+        }
+        let start = self.pos;
+
+        // The length is trusted if the stream really ends there, which is not
+        // the case when it is an indirect reference.
+        let direct_end = dict
+            .get(&b"Length"[..])
+            .and_then(Object::as_int)
+            .and_then(|len| usize::try_from(len).ok())
+            .and_then(|len| start.checked_add(len))
+            .filter(|&end| {
+                let mut p = Parser::new(self.data, end);
+                end <= self.data.len() && p.keyword(b"endstream")
+            });
+        let end = match direct_end {
+            Some(val) => val,
+            None => match find(&self.data[start..], b"endstream") {
+                Some(val) => {
+                    let mut end = start + val;
+                    if self.data[..end].ends_with(b"\r\n") {
+                        end -= 2;
+                    } else if self.data[..end].ends_with(b"\n") || self.data[..end].ends_with(b"\r")
+                    {
+                        end -= 1;
+                    }
+                    end.max(start)
+                }
+                None => {
+                    self.pos = self.data.len();
+                    return err("unterminated stream");
+                }
+            },
+        };
+        let content = self.data[start..end].to_vec();
+        self.pos = end;
+        self.keyword(b"endstream");
+        Ok(Object::Stream(dict, content))
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// The indirect objects of a document.
+struct Document {
+    objects: HashMap<u32, Object>,
+}
+
+impl Document {
+    /// Finds the indirect objects by looking for their headers instead of
+    /// reading the cross-reference table, which scanning apps often get
+    /// wrong. Objects that are defined again later, by an incremental update,
+    /// replace the earlier ones.
+    ///
+    /// The document is read in a single pass: the search for the next header
+    /// resumes where the parser stopped, even if the object was invalid, so
+    /// that no part of the document is parsed twice.
+    fn parse(data: &[u8]) -> Result<Document> {
+        let mut objects = HashMap::new();
+        let mut pos = 0;
+        while pos + 3 <= data.len() {
+            if &data[pos..(pos + 3)] != b"obj" {
+                pos += 1;
+                continue;
+            }
+            let keyword_pos = pos;
+            pos += 3;
+            if data.get(pos).is_some_and(|&b| is_regular(b)) {
+                continue;
+            }
+            let id = match object_header(data, keyword_pos) {
+                Some(val) => val,
+                None => continue,
+            };
+            let mut parser = Parser::new(data, pos);
+            if let Ok(object) = parser.indirect_object() {
+                objects.insert(id, object);
+            }
+            pos = parser.pos;
+        }
+
+        let mut doc = Document { objects };
+        doc.unpack_object_streams()?;
+        Ok(doc)
+    }
+
+    /// Adds the objects stored in object streams.
+    ///
+    /// The objects of a stream must be in the order of their offsets, and each
+    /// one is only parsed up to the start of the next one, so that a stream
+    /// that lists the same offset many times cannot make this slow.
+    fn unpack_object_streams(&mut self) -> Result<()> {
+        let streams: Vec<Object> = self
+            .objects
+            .values()
+            .filter(|o| {
+                o.as_dict()
+                    .and_then(|d| d.get(&b"Type"[..]))
+                    .and_then(Object::as_name)
+                    == Some(b"ObjStm")
+            })
+            .cloned()
+            .collect();
+        let mut total_size = 0;
+        for stream in streams {
+            let dict = stream.as_dict().unwrap();
+            let data = self.stream_data(&stream, &[], MAX_OBJECT_STREAMS_SIZE - total_size)?;
+            total_size += data.len();
+            if total_size > MAX_OBJECT_STREAMS_SIZE {
+                return err("the object streams are too large");
+            }
+            let count = self.int(dict.get(&b"N"[..])).unwrap_or(0);
+            let count = match usize::try_from(count) {
+                Ok(val) if val <= MAX_OBJECTS_PER_STREAM => val,
+                _ => return err("too many objects in object stream"),
+            };
+            let first = self.int(dict.get(&b"First"[..])).unwrap_or(0);
+            let first = usize::try_from(first).unwrap_or(usize::MAX);
+
+            let mut header = Parser::new(&data, 0);
+            let mut entries: Vec<(u32, usize)> = Vec::with_capacity(count);
+            for _ in 0..count {
+                let (id, offset) = match (header.object(0), header.object(0)) {
+                    (Ok(Object::Int(id)), Ok(Object::Int(offset))) => (id, offset),
+                    _ => return err("invalid object stream"),
+                };
+                let start = usize::try_from(offset)
+                    .ok()
+                    .and_then(|o| o.checked_add(first));
+                let previous = entries.last().map(|&(_, start)| start);
+                match (u32::try_from(id), start) {
+                    (Ok(id), Some(start))
+                        if start < data.len() && previous.is_none_or(|p| p < start) =>
+                    {
+                        entries.push((id, start))
+                    }
+                    _ => return err("invalid object stream"),
+                }
+            }
+
+            for (i, &(id, start)) in entries.iter().enumerate() {
+                if self.objects.contains_key(&id) {
+                    continue;
+                }
+                let end = entries.get(i + 1).map_or(data.len(), |&(_, next)| next);
+                if let Ok(object) = Parser::new(&data[..end], start).object(0) {
+                    self.objects.insert(id, object);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve<'a>(&'a self, mut object: &'a Object) -> &'a Object {
+        for _ in 0..MAX_DEPTH {
+            match object {
+                Object::Ref(id) => match self.objects.get(id) {
+                    Some(val) => object = val,
+                    None => return &Object::Null,
+                },
+                _ => return object,
+            }
+        }
+        &Object::Null
+    }
+
+    fn get<'a>(&'a self, dict: &'a Dict, key: &[u8]) -> &'a Object {
+        match dict.get(key) {
+            Some(val) => self.resolve(val),
+            None => &Object::Null,
+        }
+    }
+
+    fn int(&self, object: Option<&Object>) -> Option<i64> {
+        object.and_then(|o| self.resolve(o).as_int())
+    }
+
+    fn object(&self, id: u32) -> &Object {
+        self.objects
+            .get(&id)
+            .map_or(&Object::Null, |o| self.resolve(o))
+    }
+
+    /// Decodes the content of a stream, except for the filters in `keep`
+    /// which are left for the caller to decode when they are last. The
+    /// compressed data may not expand to more than `limit` bytes.
+    fn stream_data(&self, stream: &Object, keep: &[&[u8]], limit: usize) -> Result<Vec<u8>> {
+        let (dict, content) = match stream {
+            Object::Stream(dict, content) => (dict, content),
+            _ => return err("expected a stream"),
+        };
+        let filters = match self.get(dict, b"Filter") {
+            Object::Name(val) => vec![val.clone()],
+            Object::Array(val) => val
+                .iter()
+                .filter_map(|f| self.resolve(f).as_name().map(<[u8]>::to_vec))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let params: Vec<Option<&Dict>> = match self.get(dict, b"DecodeParms") {
+            Object::Array(val) => val.iter().map(|p| self.resolve(p).as_dict()).collect(),
+            other => vec![other.as_dict()],
+        };
+
+        let mut data = content.clone();
+        for (i, filter) in filters.iter().enumerate() {
+            let params = params.get(i).copied().flatten();
+            match filter.as_slice() {
+                f if i + 1 == filters.len() && keep.contains(&f) => break,
+                b"FlateDecode" | b"Fl" => {
+                    data = inflate(&data, limit)?;
+                    if let Some(params) = params {
+                        data = self.unpredict(data, params)?;
+                    }
+                }
+                f => {
+                    return error(
+                        PdfErrorKind::Unsupported,
+                        format!("unsupported filter {}", String::from_utf8_lossy(f)),
+                    )
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    /// Undoes the PNG predictors that may be applied before compressing.
+    fn unpredict(&self, data: Vec<u8>, params: &Dict) -> Result<Vec<u8>> {
+        let param = |key: &[u8], default: i64| self.int(params.get(key)).unwrap_or(default);
+        let predictor = param(b"Predictor", 1);
+        if predictor == 1 {
+            return Ok(data);
+        }
+        if predictor < 10 {
+            return error(PdfErrorKind::Unsupported, "unsupported TIFF predictor");
+        }
+        let bits_per_pixel = param(b"Colors", 1) * param(b"BitsPerComponent", 8);
+        let row_bits = param(b"Columns", 1).checked_mul(bits_per_pixel);
+        let (bpp, row_len) = match row_bits.and_then(|b| usize::try_from((b + 7) / 8).ok()) {
+            Some(row_len) if row_len > 0 && bits_per_pixel > 0 => {
+                (((bits_per_pixel + 7) / 8) as usize, row_len)
+            }
+            _ => return err("invalid predictor parameters"),
+        };
+
+        let mut out = Vec::with_capacity(data.len());
+        let mut prev = vec![0u8; row_len];
+        for chunk in data.chunks(row_len + 1) {
+            if chunk.len() < row_len + 1 {
+                break;
+            }
+            let (kind, raw) = (chunk[0], &chunk[1..]);
+            let mut row = raw.to_vec();
+            for x in 0..row_len {
+                let a = if x >= bpp { row[x - bpp] } else { 0 };
+                let b = prev[x];
+                let c = if x >= bpp { prev[x - bpp] } else { 0 };
+                let predicted = match kind {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    _ => return err("invalid PNG predictor"),
+                };
+                row[x] = row[x].wrapping_add(predicted);
+            }
+            out.extend_from_slice(&row);
+            prev = row;
+        }
+        Ok(out)
+    }
+
+    fn catalog(&self) -> Result<&Dict> {
+        // The trailer is not parsed, but there is a single catalog.
+        self.objects
+            .values()
+            .filter_map(Object::as_dict)
+            .find(|d| d.get(&b"Type"[..]).and_then(Object::as_name) == Some(b"Catalog"))
+            .map_or_else(|| err("the document has no catalog"), Ok)
+    }
+
+    /// Lists the pages in order, with the resources and rotation they inherit
+    /// from the page tree.
+    fn pages(&self) -> Result<Vec<Page<'_>>> {
+        let root = self.get(self.catalog()?, b"Pages");
+        let mut pages = Vec::new();
+        self.collect_pages(root, None, 0, 0, &mut HashSet::new(), &mut pages)?;
+        Ok(pages)
+    }
+
+    /// Walks the page tree from `node`. Every node must be visited once, so
+    /// that a tree whose nodes are shared or form a cycle is refused instead of
+    /// being walked in exponential time.
+    fn collect_pages<'a>(
+        &'a self,
+        node: &'a Object,
+        resources: Option<&'a Dict>,
+        rotate: i64,
+        depth: u32,
+        visited: &mut HashSet<u32>,
+        pages: &mut Vec<Page<'a>>,
+    ) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return err("the page tree is too deep");
+        }
+        let dict = match node.as_dict() {
+            Some(val) => val,
+            None => return err("invalid page tree"),
+        };
+        let resources = self.get(dict, b"Resources").as_dict().or(resources);
+        let rotate = self.get(dict, b"Rotate").as_int().unwrap_or(rotate);
+        match self.get(dict, b"Kids") {
+            Object::Array(kids) => {
+                for kid in kids {
+                    if let Object::Ref(id) = kid {
+                        if !visited.insert(*id) {
+                            return err("a node of the page tree is used twice");
+                        }
+                    }
+                    self.collect_pages(
+                        self.resolve(kid),
+                        resources,
+                        rotate,
+                        depth + 1,
+                        visited,
+                        pages,
+                    )?;
+                }
+            }
+            _ => {
+                if pages.len() == MAX_PAGES {
+                    return error(PdfErrorKind::TooLarge, "the document has too many pages");
+                }
+                pages.push(Page { resources, rotate });
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the object number of the largest picture in some resources,
+    /// looking into the forms that scanning apps sometimes wrap their pictures
+    /// in. Each form is only looked into once, even if it is used by several
+    /// others.
+    fn largest_image(
+        &self,
+        resources: &Dict,
+        depth: u32,
+        visited: &mut HashSet<u32>,
+    ) -> Option<u32> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let xobjects = self.get(resources, b"XObject").as_dict()?;
+        let mut largest: Option<(i64, u32)> = None;
+        for xobject in xobjects.values() {
+            // Streams are always indirect objects.
+            let id = match xobject {
+                Object::Ref(val) => *val,
+                _ => continue,
+            };
+            if !visited.insert(id) {
+                continue;
+            }
+            let dict = match self.object(id).as_dict() {
+                Some(val) => val,
+                None => continue,
+            };
+            let candidate = match self.get(dict, b"Subtype").as_name() {
+                Some(b"Image") => Some(id),
+                Some(b"Form") => self
+                    .get(dict, b"Resources")
+                    .as_dict()
+                    .and_then(|r| self.largest_image(r, depth + 1, visited)),
+                _ => None,
+            };
+            if let Some(image) = candidate {
+                let dict = self.object(image).as_dict().unwrap();
+                let area = self.int(dict.get(&b"Width"[..])).unwrap_or(0)
+                    * self.int(dict.get(&b"Height"[..])).unwrap_or(0);
+                if largest.is_none_or(|(a, _)| area > a) {
+                    largest = Some((area, image));
+                }
+            }
+        }
+        largest.map(|(_, image)| image)
+    }
+
+    /// Reads the dimensions of a picture from its dictionary.
+    fn image_dimensions(&self, dict: &Dict) -> Result<(u32, u32)> {
+        let width = self.int(dict.get(&b"Width"[..])).unwrap_or(0);
+        let height = self.int(dict.get(&b"Height"[..])).unwrap_or(0);
+        if width <= 0 || height <= 0 {
+            return err("invalid picture dimensions");
+        }
+        if width > MAX_DIMENSION as i64 || height > MAX_DIMENSION as i64 {
+            return error(PdfErrorKind::TooLarge, "picture dimensions exceed limits");
+        }
+        Ok((width as u32, height as u32))
+    }
+
+    /// Checks whether a picture is stored as a JPEG file, which is decoded as
+    /// a whole instead of sample by sample.
+    fn is_jpeg(&self, dict: &Dict) -> bool {
+        let filter = match self.get(dict, b"Filter") {
+            Object::Array(val) => val.last().map(|f| self.resolve(f)),
+            other => Some(other),
+        };
+        matches!(
+            filter.and_then(Object::as_name),
+            Some(b"DCTDecode") | Some(b"DCT")
+        )
+    }
+
+    /// Decodes the content of a picture stream, except for the JPEG
+    /// compression. The content may not expand to more than the samples of
+    /// the picture, so that a small compressed stream cannot take much more
+    /// memory than the pixels it claims to have.
+    fn image_data(&self, stream: &Object) -> Result<Vec<u8>> {
+        let (width, height) = self.image_dimensions(stream.as_dict().unwrap())?;
+        // Enough for the picture in CMYK with the predictor byte of each row,
+        // or for the headers and tables of a JPEG file.
+        let limit = (width as usize * 4 + 1) * height as usize + 64 * 1024;
+        self.stream_data(stream, &[b"DCTDecode", b"DCT"], limit)
+    }
+
+    /// Decodes a picture from the content returned by `image_data`.
+    fn decode_image(&self, stream: &Object, data: &[u8]) -> Result<DynamicImage> {
+        let dict = stream.as_dict().unwrap();
+        let (width, height) = self.image_dimensions(dict)?;
+        if self.is_jpeg(dict) {
+            return image::load_from_memory_with_format(data, ImageFormat::Jpeg)
+                .or_else(|e| err(format!("invalid JPEG picture: {}", e)));
+        }
+
+        let image_mask = matches!(self.get(dict, b"ImageMask"), Object::Bool(true));
+        let bits = if image_mask {
+            1
+        } else {
+            self.int(dict.get(&b"BitsPerComponent"[..])).unwrap_or(8)
+        };
+        // With a decode array of `[1 0]`, the samples are inverted.
+        let inverted = match self.get(dict, b"Decode") {
+            Object::Array(val) => val.first().and_then(Object::as_int) == Some(1),
+            _ => false,
+        };
+        let color_space = if image_mask {
+            ColorSpace::Gray
+        } else {
+            self.color_space(self.get(dict, b"ColorSpace"))?
+        };
+        let mut samples = unpack_samples(data, width, height, color_space.components(), bits)?;
+        // The indices in a palette are used as is.
+        if !matches!(color_space, ColorSpace::Indexed(..)) {
+            let max = (1u16 << bits) - 1;
+            for s in samples.iter_mut() {
+                let scaled = (*s as u16 * 255 / max) as u8;
+                *s = if inverted { 255 - scaled } else { scaled };
+            }
+        }
+        color_space.to_image(width, height, samples)
+    }
+
+    fn color_space(&self, object: &Object) -> Result<ColorSpace> {
+        match object {
+            Object::Name(name) => match name.as_slice() {
+                b"DeviceGray" | b"G" | b"CalGray" => Ok(ColorSpace::Gray),
+                b"DeviceRGB" | b"RGB" | b"CalRGB" => Ok(ColorSpace::Rgb),
+                b"DeviceCMYK" | b"CMYK" => Ok(ColorSpace::Cmyk),
+                _ => error(
+                    PdfErrorKind::Unsupported,
+                    format!("unsupported color space {}", String::from_utf8_lossy(name)),
+                ),
+            },
+            Object::Array(items) => {
+                let family = items
+                    .first()
+                    .map(|f| self.resolve(f))
+                    .and_then(Object::as_name);
+                match family {
+                    Some(b"ICCBased") => {
+                        let profile = items
+                            .get(1)
+                            .map(|p| self.resolve(p))
+                            .and_then(Object::as_dict);
+                        match profile.and_then(|p| self.int(p.get(&b"N"[..]))) {
+                            Some(1) => Ok(ColorSpace::Gray),
+                            Some(3) => Ok(ColorSpace::Rgb),
+                            Some(4) => Ok(ColorSpace::Cmyk),
+                            _ => err("invalid ICC based color space"),
+                        }
+                    }
+                    Some(b"Indexed") | Some(b"I") => {
+                        // The base cannot be indexed itself, which is checked
+                        // before looking into it so that a color space that
+                        // refers to itself cannot make this recurse forever.
+                        let base = match items.get(1).map(|b| self.resolve(b)) {
+                            Some(val) if !self.is_indexed(val) => self.color_space(val)?,
+                            _ => return err("invalid indexed color space"),
+                        };
+                        let lookup = match items.get(3).map(|l| self.resolve(l)) {
+                            Some(Object::Str(val)) => val.clone(),
+                            // A palette has at most 256 colors of 4 components.
+                            Some(stream @ Object::Stream(..)) => {
+                                self.stream_data(stream, &[], 256 * 4)?
+                            }
+                            _ => return err("invalid indexed color space"),
+                        };
+                        Ok(ColorSpace::Indexed(Box::new(base), lookup))
+                    }
+                    Some(b"CalGray") => Ok(ColorSpace::Gray),
+                    Some(b"CalRGB") => Ok(ColorSpace::Rgb),
+                    _ => error(PdfErrorKind::Unsupported, "unsupported color space"),
+                }
+            }
+            _ => err("missing color space"),
+        }
+    }
+
+    fn is_indexed(&self, color_space: &Object) -> bool {
+        match color_space {
+            Object::Array(items) => matches!(
+                items
+                    .first()
+                    .map(|f| self.resolve(f))
+                    .and_then(Object::as_name),
+                Some(b"Indexed") | Some(b"I")
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Reads the object number before the `obj` keyword at `pos`.
+fn object_header(data: &[u8], pos: usize) -> Option<u32> {
+    let mut i = pos;
+    let skip = |i: &mut usize, pred: fn(u8) -> bool| {
+        let end = *i;
+        while *i > 0 && pred(data[*i - 1]) {
+            *i -= 1;
+        }
+        end - *i
+    };
+    if skip(&mut i, is_whitespace) == 0
+        || skip(&mut i, |b| b.is_ascii_digit()) == 0
+        || skip(&mut i, is_whitespace) == 0
+    {
+        return None;
+    }
+    let end = i;
+    if skip(&mut i, |b| b.is_ascii_digit()) == 0 || (i > 0 && is_regular(data[i - 1])) {
+        return None;
+    }
+    std::str::from_utf8(&data[i..end]).ok()?.parse().ok()
+}
+
+fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    use miniz_oxide::inflate::TINFLStatus;
+
+    miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, limit).or_else(|e| match e {
+        TINFLStatus::HasMoreOutput => err("the compressed data is too large"),
+        e => err(format!("invalid compressed data: {:?}", e)),
+    })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Expands the packed samples of a picture to one byte per sample. For an
+/// indexed picture, the samples are the indices in the palette.
+fn unpack_samples(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    components: usize,
+    bits: i64,
+) -> Result<Vec<u8>> {
+    let per_row = width as usize * components;
+    let total = per_row * height as usize;
+    if bits == 8 {
+        if data.len() < total {
+            return err("the picture data is truncated");
+        }
+        return Ok(data[..total].to_vec());
+    }
+    let bits = match bits {
+        1 | 2 | 4 => bits as usize,
+        _ => return error(PdfErrorKind::Unsupported, "unsupported bits per component"),
+    };
+    // Rows start on a byte boundary.
+    let row_len = (per_row * bits).div_ceil(8);
+    if data.len() < row_len * height as usize {
+        return err("the picture data is truncated");
+    }
+    let max = (1 << bits) - 1;
+    let mut samples = Vec::with_capacity(total);
+    for row in data.chunks(row_len).take(height as usize) {
+        for i in 0..per_row {
+            let bit = i * bits;
+            let value = (row[bit / 8] >> (8 - bits - bit % 8)) as usize & max;
+            samples.push(value as u8);
+        }
+    }
+    Ok(samples)
+}
+
+enum ColorSpace {
+    Gray,
+    Rgb,
+    Cmyk,
+    Indexed(Box<ColorSpace>, Vec<u8>),
+}
+
+impl ColorSpace {
+    fn components(&self) -> usize {
+        match self {
+            ColorSpace::Gray | ColorSpace::Indexed(..) => 1,
+            ColorSpace::Rgb => 3,
+            ColorSpace::Cmyk => 4,
+        }
+    }
+
+    /// Builds a picture from samples expanded to one byte each.
+    fn to_image(&self, width: u32, height: u32, samples: Vec<u8>) -> Result<DynamicImage> {
+        let image = match self {
+            ColorSpace::Gray => {
+                GrayImage::from_raw(width, height, samples).map(DynamicImage::ImageLuma8)
+            }
+            ColorSpace::Rgb => {
+                RgbImage::from_raw(width, height, samples).map(DynamicImage::ImageRgb8)
+            }
+            ColorSpace::Cmyk => {
+                let rgb = samples.chunks(4).flat_map(cmyk_to_rgb).collect();
+                RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+            }
+            ColorSpace::Indexed(base, lookup) => {
+                let n = base.components();
+                let mut expanded = Vec::with_capacity(samples.len() * n);
+                for index in samples {
+                    let start = index as usize * n;
+                    match lookup.get(start..(start + n)) {
+                        Some(val) => expanded.extend_from_slice(val),
+                        None => return err("palette index out of bounds"),
+                    }
+                }
+                return base.to_image(width, height, expanded);
+            }
+        };
+        image.map_or_else(|| err("invalid picture"), Ok)
+    }
+}
+
+fn cmyk_to_rgb(cmyk: &[u8]) -> [u8; 3] {
+    let k = 255 - cmyk[3] as u16;
+    let channel = |c: u8| ((255 - c as u16) * k / 255) as u8;
+    [channel(cmyk[0]), channel(cmyk[1]), channel(cmyk[2])]
+}
+
+struct Page<'a> {
+    resources: Option<&'a Dict>,
+    rotate: i64,
+}
+
+/// The pages of a scanned document, decoded one at a time so that only one
+/// of them is in memory at once.
+pub(crate) struct ScannedPages {
+    doc: Document,
+    /// The object number of the picture of each page, and the rotation of
+    /// the page.
+    pages: Vec<(u32, i64)>,
+    next: usize,
+    /// The count of pixels of the pages decoded so far.
+    pixels: u64,
+    /// The content of the pictures that are shown on several pages, so that
+    /// they are only decompressed once. Each one is dropped after the last
+    /// page that shows it.
+    data: HashMap<u32, Vec<u8>>,
+}
+
+impl ScannedPages {
+    fn count_pixels(pixels: &mut u64, (width, height): (u32, u32)) -> Result<()> {
+        *pixels += width as u64 * height as u64;
+        if *pixels > MAX_DOCUMENT_PIXELS {
+            return error(PdfErrorKind::TooLarge, "the pages have too many pixels");
+        }
+        Ok(())
+    }
+
+    fn decode(&mut self, index: usize) -> Result<DynamicImage> {
+        let (id, rotate) = self.pages[index];
+        let doc = &self.doc;
+        let stream = doc.object(id);
+        let dict = stream.as_dict().unwrap();
+
+        // The size of a JPEG picture is only known from its own headers, the
+        // size of the others is checked before decompressing them.
+        let jpeg = doc.is_jpeg(dict);
+        if !jpeg {
+            Self::count_pixels(&mut self.pixels, doc.image_dimensions(dict)?)?;
+        }
+        let data = match self.data.remove(&id) {
+            Some(val) => val,
+            None => doc.image_data(stream)?,
+        };
+        if jpeg {
+            let dimensions = match metadata::jpeg_dimensions(&data) {
+                Some((w, h)) if w > MAX_DIMENSION || h > MAX_DIMENSION => {
+                    return error(PdfErrorKind::TooLarge, "picture dimensions exceed limits")
+                }
+                Some((w, h)) if w > 0 && h > 0 => (w, h),
+                _ => return err("invalid JPEG picture dimensions"),
+            };
+            Self::count_pixels(&mut self.pixels, dimensions)?;
+        }
+        let image = doc.decode_image(stream, &data)?;
+        if self.pages[(index + 1)..].iter().any(|&(i, _)| i == id) {
+            self.data.insert(id, data);
+        }
+
+        Ok(match rotate.rem_euclid(360) {
+            90 => image.rotate90(),
+            180 => image.rotate180(),
+            270 => image.rotate270(),
+            _ => image,
+        })
+    }
+}
+
+impl Iterator for ScannedPages {
+    type Item = Result<DynamicImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.next;
+        if index == self.pages.len() {
+            return None;
+        }
+        self.next += 1;
+        Some(self.decode(index).map_err(|e| PdfError {
+            page: Some(index + 1),
+            ..e
+        }))
+    }
+}
+
+/// Finds the pictures of the pages of a scanned document, which can then be
+/// decoded in order. A document with a page that is not a scanned picture is
+/// refused before any page is decoded.
+pub(crate) fn scanned_pages(data: &[u8]) -> Result<ScannedPages> {
+    let doc = Document::parse(data)?;
+    let pages = doc
+        .pages()?
+        .iter()
+        .enumerate()
+        .map(|(i, page)| {
+            match page
+                .resources
+                .and_then(|r| doc.largest_image(r, 0, &mut HashSet::new()))
+            {
+                Some(id) => Ok((id, page.rotate)),
+                None => Err(PdfError {
+                    kind: PdfErrorKind::NotScanned,
+                    page: Some(i + 1),
+                    message: "the page is not a scanned picture".to_string(),
+                }),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if pages.is_empty() {
+        return err("the document has no pages");
+    }
+    Ok(ScannedPages {
+        doc,
+        pages,
+        next: 0,
+        pixels: 0,
+        data: HashMap::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{GenericImageView, ImageOutputFormat};
+
+    /// Builds a document from the bodies of its objects, numbered from 1.
+    fn document(objects: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"%PDF-1.4\n".to_vec();
+        for (i, body) in objects.iter().enumerate() {
+            data.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            data.extend_from_slice(body);
+            data.extend_from_slice(b"\nendobj\n");
+        }
+        data.extend_from_slice(b"%%EOF\n");
+        data
+    }
+
+    fn stream(dict: &str, content: &[u8]) -> Vec<u8> {
+        let mut body = format!("<< {} /Length {} >>\nstream\n", dict, content.len()).into_bytes();
+        body.extend_from_slice(content);
+        body.extend_from_slice(b"\nendstream");
+        body
+    }
+
+    /// A document with a single page showing the picture in object 4, and
+    /// maybe more objects after it.
+    fn single_page(page: &str, image: Vec<u8>, more: &[Vec<u8>]) -> Vec<u8> {
+        let mut objects = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /Resources << /XObject << /Im0 4 0 R >> >> {} >>",
+                page
+            )
+            .into_bytes(),
+            image,
+        ];
+        objects.extend_from_slice(more);
+        document(&objects)
+    }
+
+    fn gradient() -> GrayImage {
+        GrayImage::from_fn(4, 3, |x, y| image::Luma([(x * 60 + y * 10) as u8]))
+    }
+
+    fn gray_image(extra: &str, content: &[u8]) -> Vec<u8> {
+        stream(
+            &format!(
+                "/Type /XObject /Subtype /Image /Width 4 /Height 3 /BitsPerComponent 8 {}",
+                extra
+            ),
+            content,
+        )
+    }
+
+    fn decode_all(data: &[u8]) -> Result<Vec<DynamicImage>> {
+        scanned_pages(data)?.collect()
+    }
+
+    fn message(result: Result<Vec<DynamicImage>>) -> String {
+        result.expect_err("the document should be refused").message
+    }
+
+    #[test]
+    fn reads_jpeg_pages() {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 8, image::Rgb([200, 30, 30])))
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let image = stream(
+            "/Type /XObject /Subtype /Image /Width 16 /Height 8 /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode",
+            &jpeg,
+        );
+        let pages = decode_all(&single_page("", image, &[])).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].dimensions(), (16, 8));
+        let pixel = pages[0].to_rgb8().get_pixel(8, 4).0;
+        assert!(pixel[0] > 150 && pixel[1] < 80 && pixel[2] < 80);
+    }
+
+    #[test]
+    fn reads_flate_pages_with_predictor() {
+        let pixels = gradient().into_raw();
+        // The first row uses the "Sub" predictor and the others the "Up" one.
+        let mut predicted = Vec::new();
+        for (y, row) in pixels.chunks(4).enumerate() {
+            if y == 0 {
+                predicted.push(1);
+                predicted.extend(
+                    (0..4).map(|x| row[x].wrapping_sub(if x > 0 { row[x - 1] } else { 0 })),
+                );
+            } else {
+                predicted.push(2);
+                predicted.extend((0..4).map(|x| row[x].wrapping_sub(pixels[(y - 1) * 4 + x])));
+            }
+        }
+        let image = gray_image(
+            "/ColorSpace /DeviceGray /Filter /FlateDecode /DecodeParms << /Predictor 15 /Colors 1 /BitsPerComponent 8 /Columns 4 >>",
+            &miniz_oxide::deflate::compress_to_vec_zlib(&predicted, 6),
+        );
+        let pages = decode_all(&single_page("", image, &[])).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].to_luma8().into_raw(), pixels);
+    }
+
+    #[test]
+    fn rotates_pages() {
+        let image = gray_image(
+            "/ColorSpace /DeviceGray /Filter /FlateDecode",
+            &miniz_oxide::deflate::compress_to_vec_zlib(&gradient().into_raw(), 6),
+        );
+        let pages = decode_all(&single_page("/Rotate 90", image, &[])).unwrap();
+        assert_eq!(pages[0].dimensions(), (3, 4));
+        assert_eq!(
+            pages[0].to_luma8(),
+            DynamicImage::ImageLuma8(gradient()).rotate90().to_luma8()
+        );
+    }
+
+    /// A document with two pages, showing the pictures in objects 5 and 6.
+    fn two_pages(first: Vec<u8>, second: Vec<u8>) -> Vec<u8> {
+        document(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 >>".to_vec(),
+            b"<< /Type /Page /Parent 2 0 R /Resources << /XObject << /Im0 5 0 R >> >> >>".to_vec(),
+            b"<< /Type /Page /Parent 2 0 R /Resources << /XObject << /Im0 6 0 R >> >> /Rotate 180 >>".to_vec(),
+            first,
+            second,
+        ])
+    }
+
+    #[test]
+    fn decompresses_shared_pictures_once() {
+        let image = gray_image(
+            "/ColorSpace /DeviceGray /Filter /FlateDecode",
+            &miniz_oxide::deflate::compress_to_vec_zlib(&gradient().into_raw(), 6),
+        );
+        let mut data = two_pages(image, Vec::new());
+        let last = find(&data, b"6 0 R").unwrap();
+        data[last] = b'5';
+        let mut pages = scanned_pages(&data).unwrap();
+        assert_eq!(pages.next().unwrap().unwrap().to_luma8(), gradient());
+        assert!(pages.data.contains_key(&5));
+        let rotated = pages.next().unwrap().unwrap();
+        assert_eq!(
+            rotated.to_luma8(),
+            DynamicImage::ImageLuma8(gradient()).rotate180().to_luma8()
+        );
+        assert!(pages.data.is_empty());
+        assert!(pages.next().is_none());
+    }
+
+    #[test]
+    fn refuses_compressed_data_larger_than_the_picture() {
+        let image = gray_image(
+            "/ColorSpace /DeviceGray /Filter /FlateDecode",
+            &miniz_oxide::deflate::compress_to_vec_zlib(&[0; 1_000_000], 6),
+        );
+        assert!(message(decode_all(&single_page("", image, &[]))).contains("too large"));
+    }
+
+    #[test]
+    fn counts_the_pixels_of_jpeg_pages_before_decoding_them() {
+        // Only the frame header of the JPEG file is valid.
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xc0, 0x00, 0x0b, 0x08];
+        jpeg.extend_from_slice(&10_000u16.to_be_bytes());
+        jpeg.extend_from_slice(&10_000u16.to_be_bytes());
+        jpeg.extend_from_slice(&[0x01, 0x01, 0x11, 0x00, 0xff, 0xda]);
+        let data = two_pages(
+            gray_image("/ColorSpace /DeviceGray", &[0; 12]),
+            stream(
+                "/Type /XObject /Subtype /Image /Width 4 /Height 3 /ColorSpace /DeviceGray /BitsPerComponent 8 /Filter /DCTDecode",
+                &jpeg,
+            ),
+        );
+        let mut pages = scanned_pages(&data).unwrap();
+        assert!(pages.next().unwrap().is_ok());
+        let error = pages
+            .next()
+            .unwrap()
+            .expect_err("the page should be refused");
+        assert_eq!(error.kind, PdfErrorKind::TooLarge);
+        assert_eq!(error.page, Some(2));
+    }
+
+    #[test]
+    fn refuses_vector_pages() {
+        let data = document(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+            b"<< /Type /Page /Parent 2 0 R /Resources << /Font << /F1 5 0 R >> >> /Contents 4 0 R >>".to_vec(),
+            stream("", b"BT /F1 12 Tf 72 712 Td (Hello) Tj ET"),
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_vec(),
+        ]);
+        let error = decode_all(&data).expect_err("the document should be refused");
+        assert_eq!(error.kind, PdfErrorKind::NotScanned);
+        assert_eq!(error.page, Some(1));
+    }
+
+    #[test]
+    fn refuses_self_referencing_indexed_color_spaces() {
+        let image = gray_image("/ColorSpace 5 0 R", &[0; 12]);
+        let data = single_page("", image, &[b"[/Indexed 5 0 R 1 <00ff>]".to_vec()]);
+        assert!(message(decode_all(&data)).contains("invalid indexed color space"));
+    }
+
+    #[test]
+    fn refuses_shared_page_tree_nodes() {
+        // Every node lists the next one twice, so walking the tree naively
+        // would visit the last node 2^30 times.
+        let mut objects = vec![b"<< /Type /Catalog /Pages 2 0 R >>".to_vec()];
+        for id in 2..32 {
+            objects
+                .push(format!("<< /Type /Pages /Kids [{0} 0 R {0} 0 R] >>", id + 1).into_bytes());
+        }
+        objects.push(b"<< /Type /Pages /Kids [] >>".to_vec());
+        assert!(message(decode_all(&document(&objects))).contains("used twice"));
+    }
+
+    #[test]
+    fn refuses_huge_object_streams() {
+        let objects = stream("/Type /ObjStm /N 1000000000 /First 4", b"6 0 ");
+        let data = single_page(
+            "",
+            gray_image("/ColorSpace /DeviceGray", &[0; 12]),
+            &[objects],
+        );
+        assert!(message(decode_all(&data)).contains("too many objects"));
+    }
+}
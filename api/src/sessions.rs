@@ -0,0 +1,236 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac, NewMac};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::Config;
+use crate::http_helpers::HttpAuthError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub(crate) enum Role {
+    #[default]
+    #[serde(rename = "student")]
+    Student,
+    #[serde(rename = "teacher")]
+    Teacher,
+}
+
+impl FromSql for Role {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_i64()? {
+            0 => Ok(Role::Student),
+            1 => Ok(Role::Teacher),
+            other => Err(FromSqlError::OutOfRange(other)),
+        }
+    }
+}
+
+impl ToSql for Role {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(match self {
+            Role::Student => 0,
+            Role::Teacher => 1,
+        }))
+    }
+}
+
+/// The data carried by a log in token. It is signed with the application
+/// secret so it cannot be forged, but it is not encrypted.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Claims {
+    /// The ID of the row in the `sessions` table.
+    #[serde(rename = "sid")]
+    pub session_id: i64,
+    #[serde(rename = "sub")]
+    pub student_id: u32,
+    /// The role of the student when the token was issued. Changing the role
+    /// of a student revokes their sessions so that it cannot get stale.
+    #[serde(default)]
+    pub role: Role,
+    /// The time at which the token was issued, as a UNIX timestamp.
+    #[serde(rename = "iat")]
+    pub issued_at: i64,
+    /// The time after which the token is refused, as a UNIX timestamp.
+    #[serde(rename = "exp")]
+    pub expires_at: i64,
+}
+
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the UNIX epoch")
+        .as_secs() as i64
+}
+
+fn sign(payload: &str, config: &Config) -> HmacSha256 {
+    let mut hmac = HmacSha256::new_varkey(&config.secret).unwrap();
+    hmac.update(payload.as_bytes());
+    hmac
+}
+
+/// Opens a new session for a student and returns a log in token for it.
+pub(crate) fn create(db: &Connection, student_id: u32, role: Role, config: &Config) -> String {
+    let issued_at = now();
+    let expires_at = issued_at + config.session_lifetime.as_secs() as i64;
+
+    // Expired sessions are useless, so this is a good time to forget them.
+    db.execute(
+        "DELETE FROM sessions WHERE expires_at < CURRENT_TIMESTAMP",
+        params![],
+    )
+    .unwrap();
+    db.execute(
+        "INSERT INTO sessions (student_id, expires_at) VALUES (?, datetime(?, 'unixepoch'))",
+        params![student_id, expires_at],
+    )
+    .unwrap();
+
+    let claims = Claims {
+        session_id: db.last_insert_rowid(),
+        student_id,
+        role,
+        issued_at,
+        expires_at,
+    };
+    let payload = base64::encode_config(
+        serde_json::to_vec(&claims).unwrap(),
+        base64::URL_SAFE_NO_PAD,
+    );
+    let sig = base64::encode_config(
+        sign(&payload, config).finalize().into_bytes(),
+        base64::URL_SAFE_NO_PAD,
+    );
+    format!("{}.{}", payload, sig)
+}
+
+/// Checks the signature and the expiry of a log in token, without looking at
+/// the database.
+pub(crate) fn decode(token: &str, config: &Config) -> Result<Claims, HttpAuthError> {
+    let dot = token.find('.').ok_or(HttpAuthError::MissingDot)?;
+    let payload = &token[..dot];
+    let sig = base64::decode_config(&token[(dot + 1)..], base64::URL_SAFE_NO_PAD)
+        .map_err(|_err| HttpAuthError::InvalidSig)?;
+    if sign(payload, config).verify(&sig).is_err() {
+        return Err(HttpAuthError::InvalidSig);
+    }
+    let claims: Claims = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|b| serde_json::from_slice(&b).ok())
+        .ok_or(HttpAuthError::InvalidClaims)?;
+    if claims.expires_at <= now() {
+        return Err(HttpAuthError::Expired);
+    }
+    Ok(claims)
+}
+
+/// Checks that the session of a decoded token was not revoked.
+pub(crate) fn check_not_revoked(db: &Connection, claims: &Claims) -> Result<(), HttpAuthError> {
+    let revoked: Option<bool> = db
+        .query_row(
+            "SELECT revoked_at IS NOT NULL FROM sessions WHERE id = ? AND student_id = ?",
+            params![claims.session_id, claims.student_id],
+            |r| r.get(0),
+        )
+        .optional()
+        .unwrap();
+    match revoked {
+        Some(false) => Ok(()),
+        // A missing row means that the session expired and was removed.
+        Some(true) | None => Err(HttpAuthError::Revoked),
+    }
+}
+
+/// Revokes a single session, for example when the student logs out.
+pub(crate) fn revoke(db: &Connection, session_id: i64) {
+    db.execute(
+        "UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP WHERE id = ? AND revoked_at IS NULL",
+        params![session_id],
+    )
+    .unwrap();
+}
+
+/// Revokes all the sessions of a student, except maybe the one that is
+/// currently in use.
+pub(crate) fn revoke_all_for_student(db: &Connection, student_id: u32, except: Option<i64>) {
+    db.execute(
+        "UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP WHERE student_id = ? AND id IS NOT ? AND revoked_at IS NULL",
+        params![student_id, except],
+    )
+    .unwrap();
+}
+
+/// How long a picture token stays valid at least. Tokens expire at the end of
+/// an hour so that listing the exercises again gives the same URLs, which
+/// lets browsers use the pictures they cached.
+const PICTURE_TOKEN_LIFETIME: i64 = 60 * 60;
+
+fn picture_payload(digest: &str, session_id: i64, expires_at: i64) -> String {
+    format!("picture:{}:{}:{}", digest, session_id, expires_at)
+}
+
+/// Creates a token that grants access to a single correction picture for a
+/// short time, as long as the session it was created for is open. Browsers
+/// cannot send an authorization header when loading an image, so this token
+/// is put in its URL instead of the log in token, which would end up in logs
+/// and in the browser history.
+pub(crate) fn picture_token(digest: &str, session_id: i64, config: &Config) -> String {
+    let expires_at = (now() / PICTURE_TOKEN_LIFETIME + 2) * PICTURE_TOKEN_LIFETIME;
+    let sig = base64::encode_config(
+        sign(&picture_payload(digest, session_id, expires_at), config)
+            .finalize()
+            .into_bytes(),
+        base64::URL_SAFE_NO_PAD,
+    );
+    format!("{}.{}.{}", session_id, expires_at, sig)
+}
+
+/// Checks that a token was created by `picture_token` for this picture and
+/// that it did not expire, and returns the session it was created for. The
+/// caller must check that the session is still open, see `open_session`.
+pub(crate) fn check_picture_token(
+    token: &str,
+    digest: &str,
+    config: &Config,
+) -> Result<i64, HttpAuthError> {
+    let mut parts = token.splitn(3, '.');
+    let (session_id, expires_at, sig) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(session_id), Some(expires_at), Some(sig)) => (session_id, expires_at, sig),
+        _ => return Err(HttpAuthError::MissingDot),
+    };
+    let session_id: i64 = session_id
+        .parse()
+        .map_err(|_err| HttpAuthError::InvalidClaims)?;
+    let expires_at: i64 = expires_at
+        .parse()
+        .map_err(|_err| HttpAuthError::InvalidClaims)?;
+    let sig = base64::decode_config(sig, base64::URL_SAFE_NO_PAD)
+        .map_err(|_err| HttpAuthError::InvalidSig)?;
+    if sign(&picture_payload(digest, session_id, expires_at), config)
+        .verify(&sig)
+        .is_err()
+    {
+        return Err(HttpAuthError::InvalidSig);
+    }
+    if expires_at <= now() {
+        return Err(HttpAuthError::Expired);
+    }
+    Ok(session_id)
+}
+
+/// Returns the student of a session and their role, if the session was not
+/// revoked, did not expire, and the student is still active.
+pub(crate) fn open_session(db: &Connection, session_id: i64) -> Result<(u32, Role), HttpAuthError> {
+    db.query_row(
+        "SELECT students.id, students.role FROM sessions INNER JOIN students ON sessions.student_id = students.id WHERE sessions.id = ? AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP AND active",
+        params![session_id],
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )
+    .optional()
+    .unwrap()
+    .ok_or(HttpAuthError::Revoked)
+}
@@ -1,7 +1,10 @@
 export interface DeletableImageProps {
   class?: string
   src: string
+  srcSet?: string
+  sizes?: string
   alt: string
+  caption?: string
   onClickDelete?: () => void
 }
 
@@ -13,6 +16,8 @@ export function DeletableImage (props: DeletableImageProps): JSX.Element {
     <div class={class_}>
       <img
         src={props.src}
+        srcset={props.srcSet}
+        sizes={props.sizes}
         alt={props.alt}
         class='img-fluid rounded'
       />
@@ -35,19 +40,24 @@ export function DeletableImage (props: DeletableImageProps): JSX.Element {
             >
               Ouvrir dans un nouvel onglet
             </a>
-            <a
-              class='dropdown-item'
-              href='#'
-              onClick={e => {
-                e.preventDefault()
-                if (props.onClickDelete !== undefined) { props.onClickDelete() }
-              }}
-            >
-              Supprimer
-            </a>
+            {props.onClickDelete !== undefined && (
+              <a
+                class='dropdown-item'
+                href='#'
+                onClick={e => {
+                  e.preventDefault()
+                  if (props.onClickDelete !== undefined) { props.onClickDelete() }
+                }}
+              >
+                Supprimer
+              </a>
+            )}
           </li>
         </ul>
       </div>
+      {props.caption !== undefined && (
+        <small class='d-block text-muted mt-1'>{props.caption}</small>
+      )}
     </div>
   )
 }
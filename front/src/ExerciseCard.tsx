@@ -2,7 +2,6 @@ import { JSX } from 'preact'
 import Masonry from 'react-masonry-css'
 
 import * as net from './net'
-import * as config from './config'
 import { DeletableImage } from './DeletableImage'
 import { Loader } from './Loader'
 import { Link } from 'react-router-dom'
@@ -10,21 +9,26 @@ import { Link } from 'react-router-dom'
 export interface Props {
   unitId: number
   exerciseIndex: number
-  correctionImages: string[]
-  reservedBy: net.Student[]
-  presentedBy: net.Student[]
+  correctionImages: net.CorrectionImage[]
+  reservedBy: net.ReservingStudent[]
+  presentedBy: net.PresentingStudent[]
+  waitlistedBy: net.WaitlistedStudent[]
   teacherCorrectedForGroupEven: boolean
   teacherCorrectedForGroupOdd: boolean
   blocked: boolean
+  noticesForMe: net.ExerciseNotice[]
   studentId: number
   studentInGroupEven: boolean
+  isTeacher: boolean
   actionPending: boolean
   onReserve?: () => void
+  onJoinWaitlist?: () => void
   onMarkPresented?: () => void
   onReset?: () => void
   onSetBlocked?: (blocked: boolean) => void
   onSetCorrectedByTeacher?: (corrected: boolean) => void
   onClickCorrectionPictureDelete?: (digest: string) => void
+  onDismissNotices?: () => void
 }
 
 function renderStudent (s: net.Student): string {
@@ -67,6 +71,40 @@ export function ExerciseCard (props: Props): JSX.Element {
     status = 'presented'
   }
 
+  for (const s of props.presentedBy) {
+    if (s.grade === undefined && s.feedback === undefined) {
+      continue
+    }
+    const title = s.id === props.studentId ? 'Mon retour' : `Retour à ${s.fullName}`
+    dlEls.push(
+      <>
+        <dt>{title}</dt>
+        <dd>
+          {s.grade !== undefined && <div>Note : {s.grade.toLocaleString('fr-FR')}/{s.maxGrade}</div>}
+          {s.feedback !== undefined && <div style={{ whiteSpace: 'pre-wrap' }}>{s.feedback}</div>}
+        </dd>
+      </>
+    )
+  }
+
+  if (props.waitlistedBy.length !== 0) {
+    dlEls.push(
+      <>
+        <dt>Liste d'attente</dt>
+        <dd>{props.waitlistedBy.map(s => `${s.position}. ${renderStudent(s)}`).join(', ')}</dd>
+      </>
+    )
+  }
+
+  const myWaitlistEntry = props.waitlistedBy.find(s => s.id === props.studentId)
+  if (myWaitlistEntry !== undefined) {
+    statusEls.push(
+      <li class='list-group-item'>
+        Vous êtes n°{myWaitlistEntry.position} sur la liste d'attente.
+      </li>
+    )
+  }
+
   if (props.teacherCorrectedForGroupEven || props.teacherCorrectedForGroupOdd) {
     let group
     if (props.teacherCorrectedForGroupEven && props.teacherCorrectedForGroupOdd) {
@@ -94,6 +132,28 @@ export function ExerciseCard (props: Props): JSX.Element {
     status = 'blocked'
   }
 
+  for (const notice of props.noticesForMe) {
+    const what = notice.kind === 'reservationCancelled'
+      ? 'Votre réservation a été annulée'
+      : 'Votre place dans la liste d\'attente a été annulée'
+    statusEls.push(
+      <li class='list-group-item list-group-item-warning d-flex align-items-start'>
+        <span class='me-auto'>
+          {what} le {notice.createdAt.toLocaleDateString('fr-FR')} car il ne faut plus faire cet exercice.
+        </span>
+        <button
+          type='button'
+          class='btn-close'
+          aria-label='Fermer'
+          disabled={props.actionPending}
+          onClick={() => {
+            if (props.onDismissNotices !== undefined) { props.onDismissNotices() }
+          }}
+        />
+      </li>
+    )
+  }
+
   let correctionPictures = null
   if (props.correctionImages.length !== 0) {
     correctionPictures = (
@@ -102,16 +162,24 @@ export function ExerciseCard (props: Props): JSX.Element {
         className='exercise-card__correction-grid'
         columnClassName='exercise-card__correction-column'
       >
-        {props.correctionImages.map((d, i) => {
+        {props.correctionImages.map((c, i) => {
+          // Only the uploader and the teachers can delete a picture.
+          const canDelete = props.isTeacher || c.createdBy?.id === props.studentId
+          const uploader = c.createdBy !== null ? c.createdBy.fullName : 'un élève dont le compte a été supprimé'
           return (
             <DeletableImage
               key={i}
               class='exercise-card__correction-image'
-              src={`${config.correctionsEndpoint}${d}.png`}
+              src={net.correctionPictureUrl(c)}
+              srcSet={net.correctionPictureSrcSet(c)}
+              sizes='(min-width: 768px) 25vw, 50vw'
               alt='Correction exercice'
-              onClickDelete={() => {
-                if (props.onClickCorrectionPictureDelete !== undefined) { props.onClickCorrectionPictureDelete(d) }
-              }}
+              caption={`Envoyée par ${uploader} le ${c.createdAt.toLocaleDateString('fr-FR')}`}
+              onClickDelete={canDelete
+                ? () => {
+                    if (props.onClickCorrectionPictureDelete !== undefined) { props.onClickCorrectionPictureDelete(c.digest) }
+                  }
+                : undefined}
             />
           )
         })}
@@ -196,12 +264,41 @@ export function ExerciseCard (props: Props): JSX.Element {
         </a>
       </li>
     )
+  } else if (myWaitlistEntry !== undefined) {
+    additionalDropdownItems.push(
+      <li>
+        <a
+          class='dropdown-item'
+          href='#'
+          onClick={e => {
+            e.preventDefault()
+            if (props.onReset !== undefined) { props.onReset() }
+          }}
+        >
+          Quitter la liste d'attente
+        </a>
+      </li>
+    )
   } else {
+    additionalDropdownItems.push(
+      <li>
+        <a
+          class='dropdown-item'
+          href='#'
+          onClick={e => {
+            e.preventDefault()
+            if (props.onJoinWaitlist !== undefined) { props.onJoinWaitlist() }
+          }}
+        >
+          Rejoindre la liste d'attente
+        </a>
+      </li>
+    )
     mainButton = (
       <button
         type='button'
         class='btn btn-primary'
-        disabled={props.actionPending}
+        disabled={props.actionPending || props.blocked}
         onClick={() => {
           if (props.onReserve !== undefined) { props.onReserve() }
         }}
@@ -242,7 +339,7 @@ export function ExerciseCard (props: Props): JSX.Element {
                     className='dropdown-item'
                     to={`/chapitres/${props.unitId}/exercices/${props.exerciseIndex + 1}/corrections/ajouter`}
                   >
-                    Ajouter la correction (jpg, png)
+                    Ajouter la correction (jpg, png, pdf)
                   </Link>
                 </li>
                 <li>
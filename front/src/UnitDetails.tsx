@@ -12,9 +12,33 @@ export interface UnitDetailsProps {
   authToken: string
   studentId: number
   studentInGroupEven: boolean
+  isTeacher: boolean
   onInvalidAuthToken?: () => void
 }
 
+type ReservationError = net.ReservationLimitError | net.ExerciseLockedError | net.ExerciseBlockedError | net.ExerciseNotFullError | net.PresentationGradedError
+
+function reservationErrorMessage (err: ReservationError): string {
+  if (err instanceof net.ExerciseNotFullError) {
+    return "Cet exercice peut encore être réservé, il n'y a pas besoin d'attendre."
+  }
+  if (err instanceof net.PresentationGradedError) {
+    return 'Votre passage sur cet exercice a déjà été noté, il ne peut plus être annulé. Demandez à un professeur.'
+  }
+  if (err instanceof net.ExerciseBlockedError) {
+    return 'Il ne faut pas faire cet exercice, il ne peut donc pas être réservé.'
+  }
+  if (err instanceof net.ExerciseLockedError) {
+    const when = err.lockedAt.toLocaleString('fr-FR', { weekday: 'long', day: 'numeric', month: 'long', hour: '2-digit', minute: '2-digit' })
+    return `Les exercices de ce chapitre ne peuvent plus être modifiés depuis le ${when}. Demandez à un professeur.`
+  }
+  if (err.rule === 'exerciseFull') {
+    const who = err.perGroup ? 'élèves de votre groupe' : 'élèves'
+    return `Cet exercice est déjà réservé par ${err.limit} ${who}, ce qui est le maximum pour ce chapitre. Vous pouvez rejoindre la liste d'attente.`
+  }
+  return `Vous avez déjà réservé ${err.limit} exercices de ce chapitre, ce qui est le maximum.`
+}
+
 export function UnitDetails (props: UnitDetailsProps): JSX.Element {
   const [exercisesWithPendingAction, setExercisesWithPendingAction] = useState<number[]>([])
 
@@ -26,6 +50,10 @@ export function UnitDetails (props: UnitDetailsProps): JSX.Element {
 
   const [exercises, setExercises] = useState<net.Exercise[] | null>(null)
   const [error, setError] = useState(false)
+  // The last deleted correction, which can still be restored from the trash.
+  // Why the last reservation was refused.
+  const [reservationError, setReservationError] = useState<string | null>(null)
+  const [deletedCorrection, setDeletedCorrection] = useState<{ exerciseIndex: number, digest: string } | null>(null)
 
   useEffect(() => {
     net.fetchExercisesInUnit(props.authToken, props.unitId)
@@ -56,9 +84,17 @@ export function UnitDetails (props: UnitDetailsProps): JSX.Element {
     const doUpdate = (makePromise: () => Promise<void>): void => {
       if (exercisesWithPendingAction.length !== 0) { return }
       setExercisesWithPendingAction(indices => [...indices, i])
+      setReservationError(null)
       makePromise()
         .then(forceUpdate)
         .catch(err => {
+          if (err instanceof net.ReservationLimitError || err instanceof net.ExerciseLockedError ||
+            err instanceof net.ExerciseBlockedError || err instanceof net.ExerciseNotFullError ||
+            err instanceof net.PresentationGradedError) {
+            setReservationError(reservationErrorMessage(err))
+            forceUpdate()
+            return
+          }
           console.error('Failed update exercise:', err)
           setError(true)
         })
@@ -71,16 +107,22 @@ export function UnitDetails (props: UnitDetailsProps): JSX.Element {
         exerciseIndex={i}
         correctionImages={e.correctionImages}
         presentedBy={e.presentedBy}
+        waitlistedBy={e.waitlistedBy}
         reservedBy={e.reservedBy}
         teacherCorrectedForGroupEven={e.teacherCorrectedForGroupEven}
         teacherCorrectedForGroupOdd={e.teacherCorrectedForGroupOdd}
         blocked={e.blocked}
+        noticesForMe={e.noticesForMe}
         studentId={props.studentId}
         studentInGroupEven={props.studentInGroupEven}
+        isTeacher={props.isTeacher}
         actionPending={exercisesWithPendingAction.includes(i)}
         onReserve={() => {
           doUpdate(async () => await net.patchExercise(props.authToken, props.unitId, i, { stateForMe: 'reserved' }))
         }}
+        onJoinWaitlist={() => {
+          doUpdate(async () => await net.patchExercise(props.authToken, props.unitId, i, { stateForMe: 'waitlisted' }))
+        }}
         onMarkPresented={() => {
           doUpdate(async () => await net.patchExercise(props.authToken, props.unitId, i, { stateForMe: 'presented' }))
         }}
@@ -93,20 +135,53 @@ export function UnitDetails (props: UnitDetailsProps): JSX.Element {
         onSetCorrectedByTeacher={corrected => {
           doUpdate(async () => await net.patchExercise(props.authToken, props.unitId, i, { teacherCorrectedForMyGroup: corrected }))
         }}
+        onDismissNotices={() => {
+          doUpdate(async () => await net.dismissExerciseNotices(props.authToken, props.unitId, i))
+        }}
         onClickCorrectionPictureDelete={digest => {
-          doUpdate(async () => await net.deleteExerciseCorrection(props.authToken, props.unitId, i, digest))
+          doUpdate(async () => {
+            await net.deleteExerciseCorrection(props.authToken, props.unitId, i, digest)
+            setDeletedCorrection({ exerciseIndex: i, digest })
+          })
         }}
       />
     )
   })
 
+  const restoreDeletedCorrection = (): void => {
+    if (deletedCorrection === null) { return }
+    const { exerciseIndex, digest } = deletedCorrection
+    setDeletedCorrection(null)
+    net.restoreExerciseCorrection(props.authToken, props.unitId, exerciseIndex, digest)
+      .then(forceUpdate)
+      .catch(err => {
+        console.error('Failed to restore correction:', err)
+        setError(true)
+      })
+  }
+
   return (
-    <Masonry
-      breakpointCols={config.pageGridColumns}
-      className='exercise-grid'
-      columnClassName='exercise-grid__column'
-    >
-      {exerciseDivs}
-    </Masonry>
+    <>
+      {reservationError !== null && (
+        <div class='alert alert-warning' role='alert'>
+          {reservationError}
+        </div>
+      )}
+      {deletedCorrection !== null && (
+        <div class='alert alert-info d-flex align-items-center' role='alert'>
+          La correction a été mise à la corbeille.
+          <button type='button' class='btn btn-link ms-auto' onClick={restoreDeletedCorrection}>
+            Annuler
+          </button>
+        </div>
+      )}
+      <Masonry
+        breakpointCols={config.pageGridColumns}
+        className='exercise-grid'
+        columnClassName='exercise-grid__column'
+      >
+        {exerciseDivs}
+      </Masonry>
+    </>
   )
 }
@@ -17,8 +17,25 @@ interface UploadProgress {
   total: number
 }
 
+function scannedPdfErrorMessage (fileName: string, err: net.ScannedPdfError): string {
+  const page = err.page !== null ? ` (page ${err.page})` : ''
+  switch (err.reason) {
+    case 'pdfNotScanned':
+      return `Le PDF ${fileName} n'est pas un document scanné${page}. Seules les pages scannées sont acceptées : envoyez plutôt des photos de la correction.`
+    case 'pdfUnsupported':
+      return `Les images du PDF ${fileName} ne sont pas dans un format pris en charge${page}. Enregistrez votre scan en JPEG ou envoyez des photos.`
+    case 'pdfTooLarge':
+      return `Le PDF ${fileName} a trop de pages ou des pages trop grandes${page}. Réduisez la résolution de votre scan ou découpez-le.`
+    default:
+      return `Le PDF ${fileName} est invalide${page}.`
+  }
+}
+
 export function UploadCorrectionForm (props: UploadCorrectionFormProps): JSX.Element {
   const [hasSelectedFiles, setHasSelectedFiles] = useState(false)
+  const [cleanUp, setCleanUp] = useState(false)
+  const [allowSimilar, setAllowSimilar] = useState(false)
+  const [hasSimilar, setHasSimilar] = useState(false)
   const [progress, setProgress] = useState<UploadProgress | null>(null)
   const [error, setError] = useState<string | null>(null)
 
@@ -39,16 +56,21 @@ export function UploadCorrectionForm (props: UploadCorrectionFormProps): JSX.Ele
     for (let i = 0; i < files.length; i++) {
       const file = files.item(i)
       if (file !== null) {
-        promises.push(net.submitExerciseCorrection(props.authToken, props.unitId, props.exerciseIndex, file)
+        promises.push(net.submitExerciseCorrection(props.authToken, props.unitId, props.exerciseIndex, file, { cleanUp, allowSimilar })
           .then(() => {})
           .catch(err => {
             console.error('Failed to upload correction:', err)
             if (err instanceof net.InvalidAuthTokenError) {
               if (props.onInvalidAuthToken !== undefined) { props.onInvalidAuthToken() }
+            } else if (err instanceof net.ScannedPdfError) {
+              setError(scannedPdfErrorMessage(file.name, err))
             } else if (err instanceof net.BadRequestError) {
               setError('Le format d\'une des photos est invalide.')
             } else if (err instanceof net.PayloadTooLargeError) {
               setError('Une des photos est trop lourde.')
+            } else if (err instanceof net.SimilarCorrectionError) {
+              setHasSimilar(true)
+              setError('Une des photos ressemble beaucoup à une correction déjà envoyée pour cet exercice.')
             } else if (err instanceof net.ConflictError) {
               // The same photo was already uploaded. This is fine.
               return
@@ -103,7 +125,10 @@ export function UploadCorrectionForm (props: UploadCorrectionFormProps): JSX.Ele
       <p class='lead'>
         Téléversez une ou plusieurs photos avec la correction de l'exercice afin
         que les autres élèves puissent la regarder après le TD.<br />
-        Chaque photo ne doit pas peser plus de <strong>5 Mio</strong>.
+        Chaque photo ne doit pas peser plus de <strong>5 Mio</strong>. Les
+        PDF issus d'un scanner ou d'une application de numérisation sont aussi
+        acceptés, jusqu'à <strong>20 Mio</strong> et 20 pages : seule l'image
+        scannée de chaque page est conservée.
       </p>
       {errorDiv}
       <form onSubmit={onSubmit}>
@@ -122,6 +147,32 @@ export function UploadCorrectionForm (props: UploadCorrectionFormProps): JSX.Ele
             }}
           />
         </div>
+        <div class='form-check mb-3'>
+          <input
+            id='clean-up-input'
+            class='form-check-input'
+            type='checkbox'
+            checked={cleanUp}
+            onChange={e => setCleanUp((e.target as HTMLInputElement).checked)}
+          />
+          <label for='clean-up-input' class='form-check-label'>
+            Nettoyer les photos de feuilles (elles sont redressées et passées en noir et blanc)
+          </label>
+        </div>
+        {hasSimilar && (
+          <div class='form-check mb-3'>
+            <input
+              id='allow-similar-input'
+              class='form-check-input'
+              type='checkbox'
+              checked={allowSimilar}
+              onChange={e => setAllowSimilar((e.target as HTMLInputElement).checked)}
+            />
+            <label for='allow-similar-input' class='form-check-label'>
+              Envoyer quand même les photos qui ressemblent à une correction existante
+            </label>
+          </div>
+        )}
         <div class={progress === null ? undefined : 'mb-3'}>
           <button
             type='submit'
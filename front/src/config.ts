@@ -1,5 +1,4 @@
 export const apiEndpoint = process.env.API_ENDPOINT ?? 'http://localhost:3000/'
-export const correctionsEndpoint = process.env.CORRECTIONS_ENDPOINT ?? 'http://localhost:8000/'
 
 export const pageGridColumns = {
   default: 3,
@@ -19,6 +19,7 @@ interface UnitDetailsRouteProps {
   units: net.Unit[]
   studentId: number
   studentInGroupEven: boolean
+  isTeacher: boolean
   onInvalidAuthToken?: () => void
 }
 
@@ -40,6 +41,7 @@ function UnitDetailsRoute (props: UnitDetailsRouteProps): JSX.Element {
         unitId={unitId}
         studentId={props.studentId}
         studentInGroupEven={props.studentInGroupEven}
+        isTeacher={props.isTeacher}
         authToken={props.authToken}
         onInvalidAuthToken={props.onInvalidAuthToken}
       />
@@ -85,7 +87,7 @@ function App (): JSX.Element {
     return <LogInForm onSuccess={setAuthToken} />
   }
 
-  const [student, setStudent] = useState<net.Student | null>(null)
+  const [student, setStudent] = useState<net.Me | null>(null)
   const [units, setUnits] = useState<net.Unit[] | null>(null)
   const [error, setError] = useState(false)
 
@@ -141,6 +143,7 @@ function App (): JSX.Element {
             units={units}
             studentId={student.id}
             studentInGroupEven={student.inGroupEven}
+            isTeacher={student.role === 'teacher'}
             authToken={authToken}
             onInvalidAuthToken={() => {
               // User has to log in again.
@@ -30,6 +30,76 @@ export class ConflictError extends Error {
   }
 }
 
+export class SimilarCorrectionError extends Error {
+  // The digest of the existing correction that looks the same.
+  duplicateOf: string
+
+  constructor (duplicateOf: string) {
+    super('A similar correction already exists.')
+    this.duplicateOf = duplicateOf
+  }
+}
+
+export class ScannedPdfError extends Error {
+  // Why the pages of the PDF document could not be extracted.
+  reason: 'pdfInvalid' | 'pdfNotScanned' | 'pdfUnsupported' | 'pdfTooLarge'
+
+  // The page that was refused, from 1, if the error is about a page.
+  page: number | null
+
+  constructor (reason: 'pdfInvalid' | 'pdfNotScanned' | 'pdfUnsupported' | 'pdfTooLarge', page: number | null) {
+    super('The PDF document is not a supported scan.')
+    this.reason = reason
+    this.page = page
+  }
+}
+
+export class ReservationLimitError extends Error {
+  // Which limit of the unit the reservation would exceed.
+  rule: 'exerciseFull' | 'tooManyReservations'
+
+  limit: number
+
+  // For 'exerciseFull', whether the limit applies to each group separately.
+  perGroup: boolean
+
+  constructor (rule: 'exerciseFull' | 'tooManyReservations', limit: number, perGroup: boolean) {
+    super('The reservation limits of the unit were reached.')
+    this.rule = rule
+    this.limit = limit
+    this.perGroup = perGroup
+  }
+}
+
+export class ExerciseLockedError extends Error {
+  // When the students of the group stopped being able to change the state of
+  // the exercise.
+  lockedAt: Date
+
+  constructor (lockedAt: Date) {
+    super('The deadline of the unit has passed.')
+    this.lockedAt = lockedAt
+  }
+}
+
+export class ExerciseNotFullError extends Error {
+  constructor () {
+    super('The exercise can still be reserved.')
+  }
+}
+
+export class ExerciseBlockedError extends Error {
+  constructor () {
+    super('The exercise is blocked.')
+  }
+}
+
+export class PresentationGradedError extends Error {
+  constructor () {
+    super('The presentation was already graded.')
+  }
+}
+
 export async function logIn (username: string, password: string): Promise<string | null> {
   const res = await fetch(`${config.apiEndpoint}log-in`, {
     method: 'POST',
@@ -73,7 +143,76 @@ function isValidStudent (o: any): o is Student {
     typeof o.inGroupEven === 'boolean'
 }
 
-export async function fetchStudentData (authToken: string): Promise<Student> {
+export interface WaitlistedStudent extends Student {
+  // The position of the student in the waitlist, starting at 1.
+  position: number
+}
+
+function isValidWaitlistedStudent (o: any): o is WaitlistedStudent {
+  return typeof o === 'object' &&
+    typeof o.position === 'number' && Number.isSafeInteger(o.position) && o.position >= 1 &&
+    isValidStudent(o)
+}
+
+export interface ReservingStudent extends Student {
+  // When the exercise was reserved, if known.
+  reservedAt: Date | null
+}
+
+export interface PresentingStudent extends ReservingStudent {
+  // When the exercise was presented, if known.
+  presentedAt: Date | null
+
+  // The grade given by the teacher, out of maxGrade, and their comment. Only
+  // sent to teachers and to the student who presented.
+  grade?: number
+  maxGrade?: number
+  feedback?: string
+}
+
+function parseOptionalDate (o: any): Date | null {
+  if (o === null) {
+    return null
+  }
+  if (typeof o !== 'string') {
+    throw new Error('Invalid JSON object')
+  }
+  return new Date(o)
+}
+
+function parseReservingStudent (o: any): ReservingStudent {
+  if (!isValidStudent(o)) {
+    throw new Error('Invalid JSON object')
+  }
+  return {
+    ...o,
+    reservedAt: parseOptionalDate((o as any).reservedAt)
+  }
+}
+
+function parsePresentingStudent (o: any): PresentingStudent {
+  if ((o.grade !== undefined && o.grade !== null && typeof o.grade !== 'number') ||
+    (o.feedback !== undefined && o.feedback !== null && typeof o.feedback !== 'string')) {
+    throw new Error('Invalid JSON object')
+  }
+  return {
+    ...parseReservingStudent(o),
+    presentedAt: parseOptionalDate(o.presentedAt),
+    grade: o.grade ?? undefined,
+    maxGrade: o.maxGrade ?? undefined,
+    feedback: o.feedback ?? undefined
+  }
+}
+
+export interface Me extends Student {
+  role: 'student' | 'teacher'
+}
+
+function isValidMe (o: any): o is Me {
+  return isValidStudent(o) && (o.role === 'student' || o.role === 'teacher')
+}
+
+export async function fetchStudentData (authToken: string): Promise<Me> {
   const res = await fetch(`${config.apiEndpoint}students/me`, {
     headers: {
       Authorization: `Bearer ${authToken}`
@@ -89,7 +228,7 @@ export async function fetchStudentData (authToken: string): Promise<Student> {
   }
 
   const json = await res.json()
-  if (!isValidStudent(json)) {
+  if (!isValidMe(json)) {
     throw new Error('Response is not valid serialized student data')
   }
 
@@ -164,10 +303,13 @@ export async function fetchUnits (authToken: string): Promise<Unit[]> {
 
 export interface Exercise {
   // The list of students who reserved this exercise.
-  reservedBy: Student[]
+  reservedBy: ReservingStudent[]
 
   // The list of students who already presented this exercise.
-  presentedBy: Student[]
+  presentedBy: PresentingStudent[]
+
+  // The students waiting for a reservation to be released, in order.
+  waitlistedBy: WaitlistedStudent[]
 
   // Whether or not this exercise should not be done for some reason.
   blocked: boolean
@@ -178,19 +320,85 @@ export interface Exercise {
   // Whether or not this exercise was corrected for the odd group.
   teacherCorrectedForGroupOdd: boolean
 
-  // A list of digests for the pictures with the correction for that
-  // exercise.
-  correctionImages: string[]
+  // The pictures with the correction for that exercise, in the order they
+  // were uploaded.
+  correctionImages: CorrectionImage[]
+
+  // The notices of the current student about this exercise, oldest first.
+  noticesForMe: ExerciseNotice[]
 }
 
-function isValidExercise (o: any): o is Exercise {
-  return typeof o === 'object' &&
-    Array.isArray(o.reservedBy) && o.reservedBy.every(isValidStudent) &&
-    Array.isArray(o.presentedBy) && o.presentedBy.every(isValidStudent) &&
+export interface ExerciseNotice {
+  // Why the student is notified: their reservation or their place in the
+  // waitlist was cancelled because the exercise was blocked.
+  kind: 'reservationCancelled' | 'waitlistCancelled'
+
+  // When it happened.
+  createdAt: Date
+}
+
+function parseExerciseNotice (o: any): ExerciseNotice {
+  if (typeof o !== 'object' ||
+    (o.kind !== 'reservationCancelled' && o.kind !== 'waitlistCancelled') ||
+    typeof o.createdAt !== 'string') {
+    throw new Error('Invalid JSON object')
+  }
+  return {
+    kind: o.kind,
+    createdAt: new Date(o.createdAt)
+  }
+}
+
+export interface CorrectionImage {
+  // The digest of the picture.
+  digest: string
+
+  // The student who uploaded the picture, null if their account was deleted
+  // since.
+  createdBy: Student | null
+
+  // When the picture was uploaded.
+  createdAt: Date
+
+  // Grants access to the picture for a short time, to be put in its URL.
+  accessToken: string
+}
+
+function parseCorrectionImage (o: any): CorrectionImage {
+  if (typeof o !== 'object' ||
+    typeof o.digest !== 'string' ||
+    !(o.createdBy === null || isValidStudent(o.createdBy)) ||
+    typeof o.createdAt !== 'string' ||
+    typeof o.accessToken !== 'string') {
+    throw new Error('Invalid JSON object')
+  }
+  return {
+    digest: o.digest,
+    createdBy: o.createdBy,
+    createdAt: new Date(o.createdAt),
+    accessToken: o.accessToken
+  }
+}
+
+function parseExercise (o: any): Exercise {
+  if (!(typeof o === 'object' &&
+    Array.isArray(o.reservedBy) &&
+    Array.isArray(o.presentedBy) &&
+    Array.isArray(o.waitlistedBy) && o.waitlistedBy.every(isValidWaitlistedStudent) &&
     typeof o.blocked === 'boolean' &&
     typeof o.teacherCorrectedForGroupEven === 'boolean' &&
     typeof o.teacherCorrectedForGroupOdd === 'boolean' &&
-    Array.isArray(o.correctionImages) && o.correctionImages.every((d: any) => typeof d === 'string')
+    Array.isArray(o.correctionImages) &&
+    Array.isArray(o.noticesForMe))) {
+    throw new Error('Invalid JSON object')
+  }
+  return {
+    ...o,
+    reservedBy: o.reservedBy.map(parseReservingStudent),
+    presentedBy: o.presentedBy.map(parsePresentingStudent),
+    correctionImages: o.correctionImages.map(parseCorrectionImage),
+    noticesForMe: o.noticesForMe.map(parseExerciseNotice)
+  }
 }
 
 export async function fetchExercisesInUnit (authToken: string, unitId: number): Promise<Exercise[]> {
@@ -209,11 +417,11 @@ export async function fetchExercisesInUnit (authToken: string, unitId: number):
   }
 
   const json = await res.json()
-  if (!Array.isArray(json) || !json.every(isValidExercise)) {
+  if (!Array.isArray(json)) {
     throw new Error('Response body is not a valid array.')
   }
 
-  return json
+  return json.map(parseExercise)
 }
 
 export async function patchExercise (authToken: string, unitId: number, exerciseIndex: number, changes: any): Promise<void> {
@@ -230,13 +438,47 @@ export async function patchExercise (authToken: string, unitId: number, exercise
     throw new InvalidAuthTokenError()
   }
 
+  if (res.status === 409) {
+    const json = await res.json()
+    if (json !== null && (json.error === 'exerciseFull' || json.error === 'tooManyReservations') &&
+      typeof json.limit === 'number') {
+      throw new ReservationLimitError(json.error, json.limit, json.perGroup === true)
+    }
+    if (json !== null && json.error === 'exerciseNotFull') {
+      throw new ExerciseNotFullError()
+    }
+    if (json !== null && json.error === 'exerciseBlocked') {
+      throw new ExerciseBlockedError()
+    }
+    if (json !== null && json.error === 'presentationGraded') {
+      throw new PresentationGradedError()
+    }
+    if (json !== null && json.error === 'exerciseLocked' && typeof json.lockedAt === 'string') {
+      throw new ExerciseLockedError(new Date(json.lockedAt))
+    }
+    throw new ConflictError()
+  }
+
   if (!res.ok) {
     throw new FailureErrorCode()
   }
 }
 
-export async function submitExerciseCorrection (authToken: string, unitId: number, exerciseIndex: number, file: File): Promise<void> {
-  const res = await fetch(`${config.apiEndpoint}units/${unitId}/exercises/${exerciseIndex}/corrections`, {
+export interface SubmitCorrectionOptions {
+  // Whether to clean up photos of paper.
+  cleanUp: boolean
+
+  // Whether to store a picture even if it looks like an existing correction.
+  allowSimilar: boolean
+}
+
+export async function submitExerciseCorrection (authToken: string, unitId: number, exerciseIndex: number, file: File, options: SubmitCorrectionOptions): Promise<void> {
+  const params = new URLSearchParams()
+  if (options.cleanUp) { params.set('clean_up', 'true') }
+  if (options.allowSimilar) { params.set('allow_similar', 'true') }
+  let url = `${config.apiEndpoint}units/${unitId}/exercises/${exerciseIndex}/corrections`
+  if (params.toString() !== '') { url += `?${params.toString()}` }
+  const res = await fetch(url, {
     method: 'POST',
     headers: {
       Authorization: `Bearer ${authToken}`
@@ -245,12 +487,23 @@ export async function submitExerciseCorrection (authToken: string, unitId: numbe
   })
 
   switch (res.status) {
-    case 400:
+    case 400: {
+      // Only the refused PDF documents come with a reason.
+      const json = await res.json().catch(() => null)
+      if (json !== null && ['pdfInvalid', 'pdfNotScanned', 'pdfUnsupported', 'pdfTooLarge'].includes(json.error)) {
+        throw new ScannedPdfError(json.error, typeof json.page === 'number' ? json.page : null)
+      }
       throw new BadRequestError()
+    }
     case 401:
       throw new InvalidAuthTokenError()
-    case 409:
+    case 409: {
+      const json = await res.json()
+      if (typeof json === 'object' && json !== null && typeof json.duplicateOf === 'string') {
+        throw new SimilarCorrectionError(json.duplicateOf)
+      }
       throw new ConflictError()
+    }
     case 413:
       throw new PayloadTooLargeError()
   }
@@ -276,3 +529,54 @@ export async function deleteExerciseCorrection (authToken: string, unitId: numbe
     throw new FailureErrorCode()
   }
 }
+
+export async function dismissExerciseNotices (authToken: string, unitId: number, exerciseIndex: number): Promise<void> {
+  const res = await fetch(`${config.apiEndpoint}units/${unitId}/exercises/${exerciseIndex}/notices`, {
+    method: 'DELETE',
+    headers: {
+      Authorization: `Bearer ${authToken}`
+    }
+  })
+
+  if (res.status === 401) {
+    throw new InvalidAuthTokenError()
+  }
+
+  if (!res.ok) {
+    throw new FailureErrorCode()
+  }
+}
+
+export async function restoreExerciseCorrection (authToken: string, unitId: number, exerciseIndex: number, pictureDigest: string): Promise<void> {
+  const res = await fetch(`${config.apiEndpoint}units/${unitId}/exercises/${exerciseIndex}/corrections/${pictureDigest}/restore`, {
+    method: 'POST',
+    headers: {
+      Authorization: `Bearer ${authToken}`
+    }
+  })
+
+  if (res.status === 401) {
+    throw new InvalidAuthTokenError()
+  }
+
+  if (!res.ok) {
+    throw new FailureErrorCode()
+  }
+}
+
+// The widths of the downscaled variants of the correction pictures.
+export const correctionPictureWidths = [320, 1280]
+
+export function correctionPictureUrl (picture: CorrectionImage, width?: number): string {
+  // Images cannot be loaded with an authorization header, so the picture
+  // comes with a token that only grants access to it.
+  let url = `${config.apiEndpoint}corrections/${picture.digest}.png?token=${encodeURIComponent(picture.accessToken)}`
+  if (width !== undefined) { url += `&size=${width}` }
+  return url
+}
+
+export function correctionPictureSrcSet (picture: CorrectionImage): string {
+  return correctionPictureWidths
+    .map(w => `${correctionPictureUrl(picture, w)} ${w}w`)
+    .join(', ')
+}
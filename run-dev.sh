@@ -11,10 +11,9 @@ kill_bg() {
 
 trap kill_bg EXIT
 
-( cd api && HTTP_PORT=3000 APP_PASSWD=test DB_PATH=db.sqlite3 APP_SECRET=aaaa CORRECTIONS_PATH=corrections cargo run ) &
-pids+=($!)
+mkdir -p api/corrections
 
-( cd front && npm run start ) &
+( cd api && HTTP_PORT=3000 APP_PASSWD=test DB_PATH=db.sqlite3 APP_SECRET=aaaa CORRECTIONS_PATH=corrections cargo run ) &
 pids+=($!)
 
-cd api && mkdir -p corrections && cd corrections && python -m http.server
+cd front && npm run start